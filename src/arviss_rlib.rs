@@ -0,0 +1,123 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+/// Environment variable that overrides discovery with the path of a specific `libarviss-<hash>.rlib`.
+pub const ARVISS_RLIB_ENV: &str = "ARVISS_RLIB";
+
+/// Environment variable that overrides the directory passed to rustc as `-L dependency=`.
+pub const ARVISS_DEPS_ENV: &str = "ARVISS_DEPS";
+
+#[derive(Error, Debug)]
+pub enum ArvissRlibError {
+    #[error("ARVISS_RLIB is set to `{path:?}` but that file does not exist")]
+    OverrideNotFound { path: PathBuf },
+
+    #[error("failed to find the current executable: {err}")]
+    CurrentExeFailed {
+        #[from]
+        err: std::io::Error,
+    },
+
+    #[error("no libarviss-*.rlib found in {searched:?}; build first or set ARVISS_RLIB")]
+    NotFound { searched: Vec<PathBuf> },
+
+    #[error("found more than one libarviss-*.rlib: {candidates:?}; set ARVISS_RLIB to the one that this build uses")]
+    Ambiguous { candidates: Vec<PathBuf> },
+}
+
+/// The arviss rlib that generated code links against, and the directory holding its own dependencies.
+#[derive(Clone, Debug)]
+pub struct ArvissRlib {
+    pub rlib: PathBuf,
+    pub deps: PathBuf,
+}
+
+impl ArvissRlib {
+    /// Finds the arviss rlib belonging to the running build.
+    ///
+    /// The `ARVISS_RLIB` (and optionally `ARVISS_DEPS`) environment variables take precedence. Otherwise the `deps`
+    /// directory of the target profile that the current executable was built into is searched, so a release binary
+    /// picks up the release rlib and a debug binary picks up the debug one. There's no telling which of several
+    /// candidates the build used, e.g., after arviss's version or features have changed, so that's an error.
+    pub fn locate() -> Result<Self, ArvissRlibError> {
+        if let Some(path) = std::env::var_os(ARVISS_RLIB_ENV) {
+            let rlib = PathBuf::from(path);
+            if !rlib.is_file() {
+                return Err(ArvissRlibError::OverrideNotFound { path: rlib });
+            }
+            return Ok(Self::with_rlib(rlib));
+        }
+
+        let exe = std::env::current_exe()?;
+        let searched = Self::search_dirs(&exe);
+        let mut candidates: Vec<PathBuf> = searched
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("libarviss-") && name.ends_with(".rlib")
+            })
+            .map(|entry| entry.path())
+            .collect();
+        candidates.sort();
+
+        match candidates.len() {
+            0 => Err(ArvissRlibError::NotFound { searched }),
+            1 => Ok(Self::with_rlib(candidates.remove(0))),
+            _ => Err(ArvissRlibError::Ambiguous { candidates }),
+        }
+    }
+
+    fn with_rlib(rlib: PathBuf) -> Self {
+        let deps = match std::env::var_os(ARVISS_DEPS_ENV) {
+            Some(deps) => PathBuf::from(deps),
+            None => rlib.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        Self { rlib, deps }
+    }
+
+    // Binaries live in `target/<profile>`, whereas tests and examples live one level further down, so look in the
+    // `deps` directory next to or above the executable.
+    fn search_dirs(exe: &Path) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(dir) = exe.parent() {
+            if dir.ends_with("deps") {
+                dirs.push(dir.to_path_buf());
+            } else {
+                dirs.push(dir.join("deps"));
+                if let Some(parent) = dir.parent() {
+                    dirs.push(parent.join("deps"));
+                }
+            }
+        }
+        dirs
+    }
+
+    /// The value for rustc's `--extern` flag.
+    pub fn extern_arg(&self) -> OsString {
+        let mut arg = OsString::from("arviss=");
+        arg.push(&self.rlib);
+        arg
+    }
+
+    /// The value for rustc's `-L` flag.
+    pub fn dependency_arg(&self) -> OsString {
+        let mut arg = OsString::from("dependency=");
+        arg.push(&self.deps);
+        arg
+    }
+
+    /// Adds the flags that rustc needs to link generated code against arviss.
+    pub fn add_args<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+        command
+            .arg("--extern")
+            .arg(self.extern_arg())
+            .arg("-L")
+            .arg(self.dependency_arg())
+    }
+}
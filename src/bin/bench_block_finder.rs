@@ -0,0 +1,141 @@
+use arviss::Address;
+use load_dll::block_finder::*;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const EBREAK: u32 = 0x00100073;
+
+fn addi(rd: u32, rs1: u32, imm: u32) -> u32 {
+    (imm << 20) | (rs1 << 15) | (rd << 7) | 0x13
+}
+
+fn bne(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    (((imm >> 12) & 1) << 31)
+        | (((imm >> 5) & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (1 << 12)
+        | (((imm >> 1) & 0xf) << 8)
+        | (((imm >> 11) & 1) << 7)
+        | 0x63
+}
+
+// Creates an image made of 16 byte chunks, each of which is three instructions followed by a branch. Most branches skip
+// forward a chunk, but every fourth one branches back into the middle of the previous chunk so that blocks get split.
+fn synthetic_image(size: usize) -> Vec<u8> {
+    let chunks = size / 16;
+    let mut image = Vec::with_capacity(chunks * 16 + 4);
+    for i in 0..chunks {
+        let branch_offset = if i % 4 == 3 { -24 } else { 20 };
+        for ins in [
+            addi(1, 1, 1),
+            addi(2, 2, 1),
+            addi(3, 3, 1),
+            bne(1, 2, branch_offset),
+        ] {
+            image.extend_from_slice(&ins.to_le_bytes());
+        }
+    }
+    image.extend_from_slice(&EBREAK.to_le_bytes());
+    image
+}
+
+// The block index that `BlockFinder` used to have, which scanned every block for each new block.
+#[derive(Default)]
+struct LinearIndex {
+    blocks: Vec<(Address, Address)>,
+}
+
+impl LinearIndex {
+    fn start_block(&mut self, addr: Address, end: Address) {
+        if self.blocks.iter().all(|&(start, _)| start != addr) {
+            let splits_block = self
+                .blocks
+                .iter_mut()
+                .find(|(start, end)| *start < addr && addr < *end);
+            if let Some(block) = splits_block {
+                block.1 = addr;
+            }
+            self.blocks.push((addr, end));
+        }
+    }
+}
+
+// The index that `BlockFinder` has now, keyed by the start of each block.
+#[derive(Default)]
+struct OrderedIndex {
+    blocks: BTreeMap<Address, Address>,
+}
+
+impl OrderedIndex {
+    fn start_block(&mut self, addr: Address, end: Address) {
+        if !self.blocks.contains_key(&addr) {
+            if let Some((_, block_end)) = self.blocks.range_mut(..addr).next_back() {
+                if addr < *block_end {
+                    *block_end = addr;
+                }
+            }
+            self.blocks.insert(addr, end);
+        }
+    }
+}
+
+// Times starting each of `blocks` in an index, which is what `BlockFinder` does for every branch target.
+fn time_index(blocks: &[Block], mut start_block: impl FnMut(Address, Address)) -> Duration {
+    let start = Instant::now();
+    for block in blocks {
+        start_block(block.start, block.end);
+    }
+    start.elapsed()
+}
+
+fn find_blocks(image: &[u8]) -> Vec<Block> {
+    match BlockFinder::with_mem(image).find_blocks(0) {
+        Ok(blocks) => blocks,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn main() {
+    // Double the image size each time. If finding blocks is linear-ish then the time per block should stay roughly
+    // constant.
+    println!("image size     blocks   time (ms)   ns/block");
+    for megabytes in [1, 2, 4, 8] {
+        let image = synthetic_image(megabytes * 1024 * 1024);
+        let start = Instant::now();
+        let blocks = find_blocks(&image);
+        let elapsed = start.elapsed();
+        println!(
+            "{:>7} MiB {:>10} {:>11.1} {:>10.1}",
+            megabytes,
+            blocks.len(),
+            elapsed.as_secs_f64() * 1000.0,
+            elapsed.as_nanos() as f64 / blocks.len() as f64
+        );
+    }
+
+    // Compare the index against the linear scan that it replaced, on the same blocks. The linear scan is quadratic, so
+    // its time per block doubles with the image, and the images are kept small enough for it to finish.
+    println!();
+    println!("image size     blocks  linear ns/block  ordered ns/block  speedup");
+    for kilobytes in [64, 128, 256, 512] {
+        let image = synthetic_image(kilobytes * 1024);
+        let blocks = find_blocks(&image);
+        let mut linear = LinearIndex::default();
+        let linear_time = time_index(&blocks, |addr, end| linear.start_block(addr, end));
+        let mut ordered = OrderedIndex::default();
+        let ordered_time = time_index(&blocks, |addr, end| ordered.start_block(addr, end));
+        println!(
+            "{:>7} KiB {:>10} {:>16.1} {:>17.1} {:>7.1}x",
+            kilobytes,
+            blocks.len(),
+            linear_time.as_nanos() as f64 / blocks.len() as f64,
+            ordered_time.as_nanos() as f64 / blocks.len() as f64,
+            linear_time.as_secs_f64() / ordered_time.as_secs_f64()
+        );
+    }
+}
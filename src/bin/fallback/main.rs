@@ -1,12 +1,34 @@
-mod fallback_compiler;
-
-use fallback_compiler::*;
-
 use arviss::Address;
 use arviss::{platforms::basic::*, DispatchRv32ic};
+use load_dll::code_watch::*;
+use load_dll::image::*;
+use load_dll::jit::*;
 use tempdir::TempDir;
 
+const USAGE: &str = "USAGE: fallback [--max-instructions N] [image]";
+
 pub fn main() {
+    // Parse the command line.
+    let mut budget = Budget::unlimited();
+    let mut path = "images/hello_world_rv32ic.flat".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-instructions" => {
+                let Some(value) = args.next().and_then(|value| value.parse().ok()) else {
+                    eprintln!("{USAGE}");
+                    std::process::exit(1);
+                };
+                budget = Budget::new(value);
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+            _ => path = arg,
+        }
+    }
+
     // Open a temporary directory that will be cleaned up at the end.
     let Ok(dir) = TempDir::new("rhtest") else {
         eprintln!("Failed to create temporary directory");
@@ -17,43 +39,59 @@ pub fn main() {
         dir.path()
     );
 
-    // Create the compiler. This particular compiler does not load everything as we're using it to test falling back to
+    // Create the compiler. Deliberately skip some blocks when loading as we're using it to test falling back to
     // interpreting.
-    let mut compiler = FallbackCompiler::new(dir);
+    let mut compiler = BlockCompiler::with_filter(dir, |index, _| index % 8 != 7);
 
-    // Load the image into a buffer and compile it.
-    let path = "images/hello_world.rv32ic";
-    let Ok(file_data) = std::fs::read(path) else {
+    // Load the image and compile it.
+    let Ok(file_data) = std::fs::read(&path) else {
         eprintln!("Failed to read file: `{}`", path);
         std::process::exit(1);
     };
-    let image = file_data.as_slice();
-    let text_size = image.len() - 4;
-
-    compiler.compile(&image[0..text_size]);
+    let image = match Image::from_bytes(&file_data) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let code_regions = image.code_regions();
+    if let Err(err) = compiler.compile(&code_regions, image.entry) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    }
 
     // Copy the image into simulator memory.
     let mut mem = BasicMem::new();
-    if let Err(addr) = mem.write_bytes(0, image) {
-        eprintln!("Failed to initialize memory at: 0x{:08x}", addr);
+    if let Err(err) = image.load_into(&mut mem) {
+        eprintln!("ERROR: {}", err);
         std::process::exit(1);
     };
 
     // Create a simulator and run it by calling the compiled functions, falling back to interpreting when we don't know
     // about the given basic block.
-    let mut addr: Address = 0;
+    let mut addr: Address = image.entry;
     let mut cpu = Cpu::with_mem(mem);
+    cpu.set_next_pc(addr);
+    cpu.transfer();
 
-    while !cpu.is_trapped() {
-        match compiler.get(addr) {
-            // Basic block found. Call the native code.
-            Some(func) => {
-                func(&mut cpu);
+    while !cpu.is_trapped() && !budget.is_exhausted() {
+        match compiler.compiled_block(addr).copied() {
+            // Basic block found. Call the native code, then drop any blocks that it wrote over.
+            Some(compiled) => {
+                (compiled.func)(&mut cpu);
+                let change = compiled.take_code_change();
                 addr = cpu.transfer();
+                let retired = compiled.retired(&code_regions, &cpu, change);
+                budget.retired += retired as u64;
+                if let Some(change) = change {
+                    compiler.code_changed(change, &cpu, &code_regions);
+                }
             }
-            // Basic block not found. Fall back to interpreting.
+            // Basic block not found. Fall back to interpreting, and compile it for next time.
             None => {
-                while !cpu.is_trapped() {
+                compiler.record_miss(addr);
+                while !cpu.is_trapped() && !budget.is_exhausted() {
                     // Fetch.
                     let ins = cpu.fetch().unwrap();
                     if compiler.get(cpu.pc()).is_some() {
@@ -61,7 +99,20 @@ pub fn main() {
                         break;
                     }
                     // Decode and dispatch.
+                    let change = CodeChange::before_interpreting(&cpu, ins);
                     cpu.dispatch(ins);
+                    budget.retired += 1;
+                    if let Some(change) = change.filter(|_| !cpu.is_trapped()) {
+                        compiler.code_changed(change, &cpu, &code_regions);
+                    }
+                }
+                match compiler.compile_missed(&code_regions) {
+                    Ok(0) => {}
+                    Ok(count) => println!("Compiled {} more blocks", count),
+                    Err(err) => {
+                        eprintln!("ERROR: {}", err);
+                        std::process::exit(1);
+                    }
                 }
             }
         };
@@ -72,6 +123,10 @@ pub fn main() {
             println!("Simulation terminated successfully")
         }
         Some(cause) => println!("{:?} at 0x{:08x}", cause, cpu.pc()),
-        None => unreachable!(),
+        None => println!(
+            "Stopped at 0x{:08x} after {} instructions",
+            cpu.pc(),
+            budget.retired
+        ),
     }
 }
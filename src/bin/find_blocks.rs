@@ -2,59 +2,203 @@ use arviss::backends::memory::basic::*;
 use arviss::{disassembler::Disassembler, DispatchRv32ic};
 
 use load_dll::block_finder::*;
+use load_dll::cfg::*;
+use load_dll::image::*;
+
+const USAGE: &str =
+    "USAGE: find_blocks [--dot] [--sweep] [--overlaps reject|duplicate|interpret] [image]";
+
+// Disassembles a block into lines of "address, encoding, code".
+fn disassemble(mem: &BasicMem, dis: &mut Disassembler, block: &Block) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut addr = block.start;
+    while addr < block.end {
+        let Ok(ins) = mem.read32(addr) else {
+            eprintln!("Failed to read memory when disassembling 0x{:08x}", addr);
+            std::process::exit(1);
+        };
+        let code = dis.dispatch(ins);
+        let is_compact = (ins & 3) != 3;
+        if is_compact {
+            // Compact instructions are 2 bytes each.
+            lines.push(format!("{:08x}     {:04x} {}", addr, ins & 0xffff, code));
+            addr += 2;
+        } else {
+            // Regular instructions are 4 bytes each.
+            lines.push(format!("{:08x} {:08x} {}", addr, ins, code));
+            addr += 4;
+        }
+    }
+    lines
+}
+
+// Escapes a string for use inside a quoted Graphviz label.
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn print_listing(cfg: &Cfg, mem: &BasicMem) {
+    let mut dis = Disassembler;
+    println!("addr     instr    code");
+    for block in cfg.blocks() {
+        let confidence = match block.confidence {
+            Confidence::Reachable => "",
+            Confidence::Swept => " (swept)",
+        };
+        println!(
+            "; --------------- Basic block: {:08x} - {:08x}{}",
+            block.start, block.end, confidence
+        );
+        for line in disassemble(mem, &mut dis, block) {
+            println!("{line}");
+        }
+    }
+}
+
+fn print_dot(cfg: &Cfg, mem: &BasicMem) {
+    let mut dis = Disassembler;
+    println!("digraph cfg {{");
+    println!("    node [shape=box, fontname=\"monospace\"];");
+
+    // One node per basic block, labelled with its disassembly.
+    for block in cfg.blocks() {
+        let mut label = format!("{:08x} - {:08x}\\l", block.start, block.end);
+        for line in disassemble(mem, &mut dis, block) {
+            label.push_str(&escape_dot(&line));
+            label.push_str("\\l");
+        }
+        let style = match block.confidence {
+            Confidence::Reachable => "solid",
+            Confidence::Swept => "dashed",
+        };
+        println!(
+            "    b{:08x} [label=\"{}\", style={}];",
+            block.start, label, style
+        );
+    }
+
+    // Edges whose target isn't known statically, or isn't a block, go to a placeholder node.
+    let mut has_unknown = false;
+    for edge in cfg.edges() {
+        let (style, color) = match edge.kind {
+            EdgeKind::Fallthrough => ("solid", "black"),
+            EdgeKind::Taken => ("solid", "green"),
+            EdgeKind::NotTaken => ("solid", "red"),
+            EdgeKind::Call => ("bold", "blue"),
+            EdgeKind::Return => ("dashed", "blue"),
+            EdgeKind::Indirect => ("dashed", "purple"),
+            EdgeKind::Trap => ("dotted", "orange"),
+        };
+        let to = match edge.to {
+            Some(to) if cfg.block(to).is_some() => format!("b{to:08x}"),
+            _ => {
+                has_unknown = true;
+                "unknown".to_string()
+            }
+        };
+        println!(
+            "    b{:08x} -> {} [label=\"{:?}\", style={}, color={}];",
+            edge.from, to, edge.kind, style, color
+        );
+    }
+    if has_unknown {
+        println!("    unknown [label=\"?\", shape=circle];");
+    }
+
+    println!("}}");
+}
 
 pub fn main() {
-    // Load the image into a buffer.
-    let path = "images/hello_world.rv32ic";
-    let Ok(file_data) = std::fs::read(path) else {
+    // Parse the command line.
+    let mut dot = false;
+    let mut sweep = false;
+    let mut overlap_policy = OverlapPolicy::default();
+    let mut path = "images/hello_world_rv32ic.flat".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" => dot = true,
+            "--sweep" => sweep = true,
+            "--overlaps" => {
+                overlap_policy = match args.next().as_deref() {
+                    Some("reject") => OverlapPolicy::Reject,
+                    Some("duplicate") => OverlapPolicy::Duplicate,
+                    Some("interpret") => OverlapPolicy::InterpretOnly,
+                    _ => {
+                        eprintln!("{USAGE}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+            _ => path = arg,
+        }
+    }
+
+    // Load the image.
+    let Ok(file_data) = std::fs::read(&path) else {
         eprintln!("Failed to read file: `{}`", path);
         std::process::exit(1);
     };
-    let image = file_data.as_slice();
-
-    // Find the basic blocks in the image.
-    let text_size = image.len() - 4; // TODO: The image needs to tell us how big its text and initialized data are.
-    let mut block_finder = BlockFinder::with_mem(&image[..text_size]);
-    let blocks = match block_finder.find_blocks(0) {
-        Ok(blocks) => blocks,
+    let image = match Image::from_bytes(&file_data) {
+        Ok(image) => image,
         Err(err) => {
             eprintln!("ERROR: {}", err);
             std::process::exit(1);
         }
     };
 
+    // Find the basic blocks in the image's code.
+    let mut block_finder = BlockFinder::with_regions(image.code_regions())
+        .with_data_regions(image.data_regions())
+        .with_overlap_policy(overlap_policy)
+        .with_linear_sweep(sweep);
+    if let Err(err) = block_finder.add_entry_points(&image.entry_points()) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    }
+    let cfg = block_finder.cfg();
+
     // Copy the image into memory.
     let mut mem = BasicMem::new();
-    if let Err(addr) = mem.write_bytes(0, image) {
-        eprintln!("Failed to initialize memory at: 0x{:08x}", addr);
+    if let Err(err) = image.load_into(&mut mem) {
+        eprintln!("ERROR: {}", err);
         std::process::exit(1);
     };
 
-    // Disassemble each block for visual evidence that it's working.
-    let mut dis = Disassembler;
-    println!("addr     instr    code");
-    for block in blocks {
-        println!(
-            "; --------------- Basic block: {:08x} - {:08x}",
-            block.start, block.end
-        );
-        let mut addr = block.start;
-        while addr < block.end {
-            let Ok(ins) = mem.read32(addr) else {
-                eprintln!("Failed to read memory when disassembling 0x{:08x}", addr);
-                std::process::exit(1);
-            };
-            let code = dis.dispatch(ins);
-            let is_compact = (ins & 3) != 3;
-            if is_compact {
-                // Compact instructions are 2 bytes each.
-                println!("{:08x}     {:04x} {}", addr, ins & 0xffff, code);
-                addr += 2;
-            } else {
-                // Regular instructions are 4 bytes each.
-                println!("{:08x} {:08x} {}", addr, ins, code);
-                addr += 4;
-            }
+    if dot {
+        // Render the control-flow graph, e.g., with `find_blocks --dot | dot -Tsvg > cfg.svg`.
+        print_dot(&cfg, &mem);
+    } else {
+        // Disassemble each block for visual evidence that it's working.
+        print_listing(&cfg, &mem);
+
+        // Summarize the functions.
+        for function in cfg.functions() {
+            println!(
+                "; Function at {:08x}: {} blocks",
+                function.entry,
+                function.blocks.len()
+            );
+        }
+
+        // Report any jumps that leave the code entirely, as they're either bugs or calls into code that we don't have.
+        for jump in block_finder.unmapped_jumps() {
+            println!(
+                "; Jump from {:08x} to unmapped address {:08x}",
+                jump.source, jump.target
+            );
+        }
+
+        // Report any blocks that start in the middle of another instruction.
+        for overlap in block_finder.overlaps() {
+            println!(
+                "; Block at {:08x} overlaps the instruction at {:08x}",
+                overlap.addr, overlap.instruction
+            );
         }
     }
 }
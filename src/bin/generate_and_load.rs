@@ -1,6 +1,7 @@
 use arviss::decoding::Reg;
 use arviss::platforms::basic::*;
 use libloading::{Library, Symbol};
+use load_dll::arviss_rlib::*;
 use std::fs::File;
 use std::io::{self, BufRead, Write};
 use std::process::Command;
@@ -32,14 +33,15 @@ pub fn main() -> Result<(), io::Error> {
 
     // Compile it to a .so.
     let filename = dir.path().join("demo.rs").to_string_lossy().to_string();
+    let arviss =
+        ArvissRlib::locate().map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
     let mut command = Command::new("rustc");
-    let run = command
+    let run = arviss
+        .add_args(&mut command)
         .current_dir(dir.path())
         .arg("--edition=2021")
         .arg("--crate-type")
         .arg("cdylib")
-        .arg("--extern")
-        .arg("arviss=/home/rod/projects/learn_rust/100days/load_dll/target/release/deps/libarviss-3f92a38f6024ae90.rlib")
         .arg("-C")
         .arg("opt-level=2")
         .arg("-C")
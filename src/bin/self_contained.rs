@@ -0,0 +1,131 @@
+use arviss::platforms::basic::*;
+use arviss::Address;
+use libloading::{Library, Symbol};
+use load_dll::block_finder::*;
+use load_dll::block_writer::*;
+use load_dll::dll_api::*;
+use load_dll::image::*;
+use load_dll::jit::Cpu;
+use std::collections::HashMap;
+use std::fs::File;
+use std::process::Command;
+use tempdir::TempDir;
+
+type ArvissAbiVersionFunc = extern "C" fn() -> u32;
+
+pub fn main() {
+    // Open a temporary directory that will be cleaned up at the end.
+    let Ok(dir) = TempDir::new("rhtest") else {
+        eprintln!("Failed to create temporary directory");
+        std::process::exit(1);
+    };
+    println!(
+        "Look in {:?} for the generated code and library",
+        dir.path()
+    );
+
+    // Load the image.
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "images/hello_world_rv32ic.flat".to_string());
+    let Ok(file_data) = std::fs::read(&path) else {
+        eprintln!("Failed to read file: `{}`", path);
+        std::process::exit(1);
+    };
+    let image = match Image::from_bytes(&file_data) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Find the basic blocks in the image's code.
+    let mut block_finder = BlockFinder::with_regions(image.code_regions());
+    let blocks = match block_finder.find_blocks(image.entry) {
+        Ok(blocks) => blocks,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Generate a self-contained Rust module containing source for each basic block.
+    let file_path = dir.path().join("demo.rs");
+    let Ok(mut f) = File::create(&file_path) else {
+        eprintln!("Failed to create file");
+        std::process::exit(1);
+    };
+    let mut block_writer = BlockWriter::with_regions(image.code_regions(), Runtime::SelfContained);
+    if let Err(err) = block_writer.write_blocks(&mut f, &blocks) {
+        eprintln!("Failed to write blocks: {err}");
+        std::process::exit(1);
+    }
+    drop(f);
+
+    // Compile the module to a .so. Note that there is no `--extern arviss=...`.
+    let Ok(run) = Command::new("rustc")
+        .current_dir(dir.path())
+        .arg("--edition=2021")
+        .arg("--crate-type")
+        .arg("cdylib")
+        .arg("-Cpanic=abort")
+        .arg("-C")
+        .arg("opt-level=2")
+        .arg(&file_path)
+        .status()
+    else {
+        eprintln!("Failed to compile");
+        std::process::exit(1);
+    };
+    assert!(run.success());
+
+    // Load the library and check that it was built against the same ABI as us.
+    let library_path = dir.path().join(libloading::library_filename("demo"));
+    let lib = unsafe { Library::new(library_path).unwrap() };
+    let abi_version = unsafe {
+        let abi_version: Symbol<ArvissAbiVersionFunc> = lib.get(b"arviss_abi_version").unwrap();
+        abi_version()
+    };
+    if abi_version != ABI_VERSION {
+        eprintln!("Library has ABI version {abi_version} but expected {ABI_VERSION}");
+        std::process::exit(1);
+    }
+
+    // Load the functions from the library.
+    let block_map = unsafe {
+        let mut block_map = HashMap::new();
+        for block in blocks {
+            let symbol = format!("block_{:08x}_{:08x}", block.start, block.end);
+            let basic_block_fn: Symbol<NativeBlockFunc> = lib.get(symbol.as_bytes()).unwrap();
+            block_map.insert(block.start, *basic_block_fn);
+        }
+        block_map
+    };
+
+    // Copy the image into simulator memory.
+    let mut mem = BasicMem::new();
+    if let Err(err) = image.load_into(&mut mem) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    };
+
+    // Create a simulator and run it by calling the compiled functions.
+    let mut addr: Address = image.entry;
+    let mut cpu = Cpu::with_mem(mem);
+    cpu.set_next_pc(addr);
+    cpu.transfer();
+    while !cpu.is_trapped() {
+        let run_one = block_map[&addr];
+        call_native(run_one, &mut cpu);
+        addr = cpu.transfer();
+    }
+
+    match cpu.trap_cause() {
+        Some(TrapCause::Breakpoint) => {
+            println!("Simulation terminated successfully")
+        }
+        Some(cause) => println!("{:?} at 0x{:08x}", cause, addr),
+        None => unreachable!(),
+    }
+}
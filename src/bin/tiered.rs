@@ -0,0 +1,107 @@
+use arviss::platforms::basic::*;
+use load_dll::compile_worker::*;
+use load_dll::image::*;
+use load_dll::jit::*;
+use load_dll::tiered::*;
+use tempdir::TempDir;
+
+const USAGE: &str = "USAGE: tiered [--threshold N] [--background] [image]";
+
+pub fn main() {
+    // Parse the command line.
+    let mut threshold = DEFAULT_HOT_THRESHOLD;
+    let mut background = false;
+    let mut path = "images/hello_world_rv32ic.flat".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => {
+                let Some(value) = args.next().and_then(|value| value.parse().ok()) else {
+                    eprintln!("{USAGE}");
+                    std::process::exit(1);
+                };
+                threshold = value;
+            }
+            "--background" => background = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+            _ => path = arg,
+        }
+    }
+
+    // Open a temporary directory that will be cleaned up at the end.
+    let Ok(dir) = TempDir::new("rhtest") else {
+        eprintln!("Failed to create temporary directory");
+        std::process::exit(1);
+    };
+
+    // Load the image. Nothing is compiled up front.
+    let Ok(file_data) = std::fs::read(&path) else {
+        eprintln!("Failed to read file: `{}`", path);
+        std::process::exit(1);
+    };
+    let image = match Image::from_bytes(&file_data) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Copy the image into simulator memory.
+    let mut mem = BasicMem::new();
+    if let Err(err) = image.load_into(&mut mem) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    };
+
+    // Run it, starting in the interpreter.
+    let mut cpu = Cpu::with_mem(mem);
+    cpu.set_next_pc(image.entry);
+    cpu.transfer();
+    let code_regions = image.code_regions();
+    let mut runtime = if background {
+        // Compile on a worker thread while the interpreter keeps going.
+        match AsyncCompiler::new(dir, &code_regions) {
+            Ok(compiler) => TieredRuntime::with_background_compiler(compiler, threshold),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        TieredRuntime::with_threshold(BlockCompiler::new(dir), threshold)
+    };
+    if let Err(err) = runtime.run(&mut cpu, &code_regions) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    }
+
+    match cpu.trap_cause() {
+        Some(TrapCause::Breakpoint) => {
+            println!("Simulation terminated successfully")
+        }
+        Some(cause) => println!("{:?} at 0x{:08x}", cause, cpu.pc()),
+        None => println!("Stopped at 0x{:08x}", cpu.pc()),
+    }
+
+    let stats = runtime.stats();
+    println!(
+        "Interpreted {} instructions, ran {} natively in {} calls",
+        stats.interpreted_instructions, stats.native_instructions, stats.native_calls
+    );
+    println!(
+        "Compiled {} blocks in {:.1} ms",
+        stats.blocks_compiled,
+        stats.compile_time.as_secs_f64() * 1000.0
+    );
+    match stats.time_to_first_native {
+        Some(elapsed) => println!(
+            "Time to first native: {:.1} ms",
+            elapsed.as_secs_f64() * 1000.0
+        ),
+        None => println!("Nothing ran natively"),
+    }
+}
@@ -0,0 +1,86 @@
+use load_dll::flat_image::*;
+use std::fs::File;
+
+const USAGE: &str = "USAGE: wrap_image <input.rv32i|input.rv32ic> <output> [--data-len N] [--bss-len N] [--load-addr A] [--entry A]";
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+pub fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (Some(input), Some(output)) = (args.first(), args.get(1)) else {
+        eprintln!("{USAGE}");
+        std::process::exit(1);
+    };
+
+    // The ISA comes from the extension of the raw blob.
+    let isa = if input.ends_with(".rv32ic") {
+        ISA_RV32I | ISA_RV32C
+    } else if input.ends_with(".rv32i") {
+        ISA_RV32I
+    } else {
+        eprintln!("Input must be a .rv32i or .rv32ic file: `{}`", input);
+        std::process::exit(1);
+    };
+
+    // Everything else comes from the command line, as a raw blob can't tell us.
+    let mut data_len = 0;
+    let mut bss_len = 0;
+    let mut load_addr = 0;
+    let mut entry = None;
+    for option in args[2..].chunks(2) {
+        let [name, value] = option else {
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        };
+        let Some(value) = parse_number(value) else {
+            eprintln!("Not a number: `{}`", value);
+            std::process::exit(1);
+        };
+        match name.as_str() {
+            "--data-len" => data_len = value,
+            "--bss-len" => bss_len = value,
+            "--load-addr" => load_addr = value,
+            "--entry" => entry = Some(value),
+            _ => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let Ok(file_data) = std::fs::read(input) else {
+        eprintln!("Failed to read file: `{}`", input);
+        std::process::exit(1);
+    };
+    let Some(text_len) = file_data.len().checked_sub(data_len as usize) else {
+        eprintln!("Data length {} is larger than the image", data_len);
+        std::process::exit(1);
+    };
+    let (text, data) = file_data.split_at(text_len);
+
+    let image = FlatImage::new(
+        isa,
+        load_addr,
+        entry.unwrap_or(load_addr),
+        text,
+        data,
+        bss_len,
+    );
+    let Ok(mut f) = File::create(output) else {
+        eprintln!("Failed to create file: `{}`", output);
+        std::process::exit(1);
+    };
+    if let Err(err) = image.write(&mut f) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    }
+    println!(
+        "Wrote {}: text {} bytes, data {} bytes, bss {} bytes, loaded at 0x{:08x}, entry 0x{:08x}",
+        output, image.header.text_len, data_len, bss_len, load_addr, image.header.entry
+    );
+}
@@ -1,56 +1,130 @@
-mod compiler;
-
-use compiler::*;
-
 use arviss::platforms::basic::*;
-use arviss::Address;
+use arviss::{Address, DispatchRv32ic};
+use load_dll::block_finder::*;
+use load_dll::code_watch::*;
+use load_dll::image::*;
+use load_dll::jit::*;
+use load_dll::library_cache::*;
 use std::io::{self, BufRead};
 use tempdir::TempDir;
 
+const USAGE: &str =
+    "USAGE: write_blocks [--no-cache] [--superblocks] [--max-instructions N] [image]";
+
 pub fn main() {
+    // Parse the command line.
+    let mut use_cache = true;
+    let mut superblocks = false;
+    let mut budget = Budget::unlimited();
+    let mut path = "images/hello_world_rv32ic.flat".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-cache" => use_cache = false,
+            "--superblocks" => superblocks = true,
+            "--max-instructions" => {
+                let Some(value) = args.next().and_then(|value| value.parse().ok()) else {
+                    eprintln!("{USAGE}");
+                    std::process::exit(1);
+                };
+                budget = Budget::new(value);
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+            _ => path = arg,
+        }
+    }
+
     // Open a temporary directory that will be cleaned up at the end.
     let Ok(dir) = TempDir::new("rhtest") else {
         eprintln!("Failed to create temporary directory");
         std::process::exit(1);
     };
-    println!(
-        "Look in {:?} for the generated code and library",
-        dir.path()
-    );
 
-    // Create the compiler.
-    let mut compiler = Compiler::new(dir);
+    // Create the compiler. Unless told otherwise, it reuses libraries from previous runs of the same code.
+    let cache = if use_cache {
+        LibraryCache::locate().ok()
+    } else {
+        None
+    };
+    let mut compiler = match cache {
+        Some(cache) => {
+            println!(
+                "Look in {:?} for the generated code and libraries",
+                cache.dir()
+            );
+            BlockCompiler::new(dir)
+                .with_superblocks(superblocks)
+                .with_cache(cache)
+        }
+        None => {
+            println!(
+                "Look in {:?} for the generated code and library",
+                dir.path()
+            );
+            BlockCompiler::new(dir).with_superblocks(superblocks)
+        }
+    };
 
-    // Load the image into a buffer and compile it.
-    let path = "images/hello_world.rv32ic";
-    let Ok(file_data) = std::fs::read(path) else {
+    // Load the image and compile it.
+    let Ok(file_data) = std::fs::read(&path) else {
         eprintln!("Failed to read file: `{}`", path);
         std::process::exit(1);
     };
-    let image = file_data.as_slice();
-    let text_size = image.len() - 4; // TODO: The image needs to tell us how big its text and initialized data are.
-    compiler.compile(&image[0..text_size]);
+    let image = match Image::from_bytes(&file_data) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let code_regions = image.code_regions();
+    if let Err(err) = compiler.compile(&code_regions, image.entry) {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    }
 
     // Copy the image into simulator memory.
     let mut mem = BasicMem::new();
-    if let Err(addr) = mem.write_bytes(0, image) {
-        eprintln!("Failed to initialize memory at: 0x{:08x}", addr);
+    if let Err(err) = image.load_into(&mut mem) {
+        eprintln!("ERROR: {}", err);
         std::process::exit(1);
     };
 
     // TODO: What if we have multiple images?
 
     // Create a simulator and run it by calling the compiled functions.
-    let mut addr: Address = 0;
+    let mut addr: Address = image.entry;
     let mut cpu = Cpu::with_mem(mem);
-    while !cpu.is_trapped() {
-        // TODO: Fall back to interpreting if we can't find a basic block in the map ... or compilation if
-        // we're feeling adventurous.
-        let run_one = compiler.get(addr).unwrap();
-        run_one(&mut cpu);
-        addr = cpu.transfer();
+    cpu.set_next_pc(addr);
+    cpu.transfer();
+    while !cpu.is_trapped() && !budget.is_exhausted() {
+        // Compile any block that we haven't seen before, e.g., the target of an indirect jump.
+        if compiler.get(addr).is_none() {
+            compiler.record_miss(addr);
+            if let Err(err) = compiler.compile_missed(&code_regions) {
+                eprintln!("ERROR: {}", err);
+                std::process::exit(1);
+            }
+        }
+
+        // Run blocks until we get to one that isn't compiled, going directly from one to the next where possible. If
+        // there's still no block, e.g., because the code was written to, then interpret it instead.
+        addr = match compiler.get(addr) {
+            Some(_) => compiler.run_chained(&mut cpu, &mut budget, &code_regions, addr),
+            None => interpret(&mut cpu, &mut budget, &mut compiler, &code_regions),
+        };
+    }
+    if cpu.is_trapped() {
+        println!("Trapped at 0x{:08x}", addr);
+    } else {
+        println!(
+            "Stopped at 0x{:08x} after {} instructions",
+            addr, budget.retired
+        );
     }
-    println!("Trapped at 0x{:08x}", addr);
 
     // Give the user (me) an opportunity to disassemble the binary.
     let stdin = io::stdin();
@@ -58,3 +132,35 @@ pub fn main() {
         println!("{}", line.unwrap());
     }
 }
+
+// Interprets until control reaches a compiled block or jumps, the CPU traps, or the budget runs out, and returns the
+// address that it stopped at. Stopping at jumps gives the caller the chance to compile their targets.
+fn interpret(
+    cpu: &mut Cpu,
+    budget: &mut Budget,
+    compiler: &mut BlockCompiler,
+    code_regions: &[CodeRegion],
+) -> Address {
+    while !cpu.is_trapped() && !budget.is_exhausted() {
+        let Ok(ins) = cpu.fetch() else {
+            eprintln!("No code at 0x{:08x}", cpu.pc());
+            std::process::exit(1);
+        };
+        let pc = cpu.pc();
+        let change = CodeChange::before_interpreting(cpu, ins);
+        cpu.dispatch(ins);
+        budget.retired += 1;
+        if let Some(change) = change.filter(|_| !cpu.is_trapped()) {
+            compiler.code_changed(change, cpu, code_regions);
+        }
+        if cpu.is_trapped() {
+            break;
+        }
+        let next = cpu.transfer();
+        let in_sequence = next == pc.wrapping_add(if (ins & 3) == 3 { 4 } else { 2 });
+        if !in_sequence || compiler.get(next).is_some() {
+            return next;
+        }
+    }
+    cpu.pc()
+}
@@ -1,117 +1,709 @@
+use crate::cfg::*;
 use crate::read_instruction::*;
+use crate::register_tracker::*;
+use arviss::decoding::Reg;
 use arviss::{Address, DispatchRv32ic, HandleRv32c, HandleRv32i};
-use std::ops::{Index, IndexMut};
+use std::collections::{BTreeMap, BTreeSet};
 use thiserror::Error;
 
+/// How sure `BlockFinder` is that a block really is code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Confidence {
+    /// Reachable by following control flow from an entry point.
+    #[default]
+    Reachable,
+    /// Found by a linear sweep, or read from a jump table whose size isn't known. It decodes without illegal
+    /// instructions, but nothing that was found is known to jump to it.
+    Swept,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub struct Block {
     pub start: Address, // Address of the first instruction in the basic block.
     pub end: Address, // Address of the instruction following the last instruction in the basic block.
+    pub confidence: Confidence,
 }
 
 const OPEN_BLOCK_SENTINEL: Address = 0;
 
-impl Block {
-    fn new(start: Address) -> Self {
-        Block {
-            start,
-            end: OPEN_BLOCK_SENTINEL,
+// A register that's known to be below a limit on entry to a block.
+type Bound = (Reg, u32);
+
+// Stops runaway reads when the size of a jump table isn't known, and it isn't followed by something that obviously
+// isn't a code address.
+const MAX_JUMP_TABLE_ENTRIES: usize = 1024;
+
+/// A region of guest memory containing code, mapped at `base`.
+#[derive(Clone, Copy, Debug)]
+pub struct CodeRegion<'a> {
+    pub base: Address,
+    pub mem: &'a [u8],
+    pub is_writable: bool, // True if the guest may modify it, so what's in `mem` now may not last.
+}
+
+impl<'a> CodeRegion<'a> {
+    pub fn new(base: Address, mem: &'a [u8]) -> Self {
+        Self {
+            base,
+            mem,
+            is_writable: false,
         }
     }
+
+    /// Marks the region as one that the guest may write to, so that `BlockFinder` doesn't take the words in it, e.g.,
+    /// jump tables, as constants.
+    pub fn with_writable(mut self, is_writable: bool) -> Self {
+        self.is_writable = is_writable;
+        self
+    }
+
+    pub fn contains(&self, addr: Address) -> bool {
+        addr >= self.base && ((addr - self.base) as usize) < self.mem.len()
+    }
+
+    pub(crate) fn read_instruction(&self, addr: Address) -> Result<u32, Address> {
+        read_instruction(self.mem, addr.wrapping_sub(self.base)).map_err(|_| addr)
+    }
+
+    pub(crate) fn read_word(&self, addr: Address) -> Option<u32> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        let bytes = self.mem.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+/// Reads an instruction from whichever of `regions` contains `addr`.
+pub(crate) fn read_instruction_from(regions: &[CodeRegion], addr: Address) -> Result<u32, Address> {
+    regions
+        .iter()
+        .find(|region| region.contains(addr))
+        .ok_or(addr)?
+        .read_instruction(addr)
+}
+
+// Reads a word from whichever of the regions that the guest can't write to contains `addr`.
+fn read_constant_word(
+    regions: &[CodeRegion],
+    data_regions: &[CodeRegion],
+    addr: Address,
+) -> Option<u32> {
+    regions
+        .iter()
+        .chain(data_regions)
+        .filter(|region| !region.is_writable)
+        .find_map(|region| region.read_word(addr))
+}
+
+/// Counts the instructions in [start, end), which isn't simply its size when there are compressed instructions.
+pub fn count_instructions(regions: &[CodeRegion], start: Address, end: Address) -> u32 {
+    let mut count = 0;
+    let mut addr = start;
+    while addr < end {
+        let Ok(ins) = read_instruction_from(regions, addr) else {
+            break;
+        };
+        addr += if (ins & 3) == 3 { 4 } else { 2 };
+        count += 1;
+    }
+    count
+}
+
+/// A jump, branch or fallthrough to an address that isn't in any of the code regions.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct UnmappedJump {
+    pub source: Address, // Address of the instruction that transfers control.
+    pub target: Address, // Address that it transfers control to.
+}
+
+/// A block start that lies inside an instruction that was decoded from an earlier address. This can happen with
+/// compressed instructions, as a 4-byte instruction can be decoded starting at any 2-byte boundary.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct OverlappingDecode {
+    pub addr: Address,        // Start of the block.
+    pub instruction: Address, // Address of the instruction that it overlaps.
+}
+
+/// What `BlockFinder` should do when it finds an overlapping decode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// Fail with `BlockFinderError::OverlappingDecode`.
+    Reject,
+    /// Decode the bytes both ways, giving blocks that overlap each other.
+    Duplicate,
+    /// Don't create a block for the overlapping address, so that it's left to the interpreter.
+    #[default]
+    InterpretOnly,
 }
 
 pub struct BlockFinder<'a> {
     addr: Address,
-    mem: &'a [u8],
-    known_blocks: Vec<Block>,
-    open_blocks: Vec<usize>,
-    current_block: usize,
+    regions: Vec<CodeRegion<'a>>,
+    data_regions: Vec<CodeRegion<'a>>, // Read-only data that may contain jump tables.
+    known_blocks: BTreeMap<Address, Address>, // Maps the start of each block to its end.
+    swept_blocks: BTreeSet<Address>,   // Starts of the blocks that the sweep found or were guessed.
+    changed_blocks: BTreeSet<Address>, // Starts of the blocks that are new or have changed since the last call.
+    linear_sweep: bool,
+    sweeping: bool,
+    saw_illegal: bool,
+    unmapped_jumps: BTreeSet<UnmappedJump>,
+    overlaps: BTreeSet<OverlappingDecode>,
+    overlap_policy: OverlapPolicy,
+    rejected_overlap: Option<OverlappingDecode>,
+    exits: BTreeMap<Address, Vec<(Option<Address>, EdgeKind)>>, // Control flow out of each terminating instruction.
+    function_entries: BTreeSet<Address>,
+    registers: RegisterTracker,
+    entry_bounds: BTreeMap<Address, Bound>,
+    open_blocks: Vec<Address>,
+    current_block: Address,
+}
+
+// The calling convention uses `ra` as the link register, with `t0` as an alternate, e.g., for millicode.
+#[inline]
+fn is_link_register(reg: Reg) -> bool {
+    reg == Reg::RA || reg == Reg::T0
 }
 
 #[derive(Error, Debug)]
 pub enum BlockFinderError {
     #[error("memory read failed at 0x{addr:08x}")]
     MemoryReadFailed { addr: Address },
+
+    #[error("entry point 0x{addr:08x} is not in a code region")]
+    EntryPointUnmapped { addr: Address },
+
+    #[error("block at 0x{addr:08x} overlaps the instruction at 0x{instruction:08x}")]
+    OverlappingDecode { addr: Address, instruction: Address },
 }
 
 impl<'a> BlockFinder<'a> {
+    /// Creates a block finder for code that is mapped at address zero.
     pub fn with_mem(mem: &'a [u8]) -> Self {
+        Self::with_regions(vec![CodeRegion::new(0, mem)])
+    }
+
+    /// Creates a block finder for code that is mapped into one or more regions of the address space.
+    pub fn with_regions(regions: Vec<CodeRegion<'a>>) -> Self {
         Self {
             addr: 0,
-            mem,
-            known_blocks: Vec::new(),
+            regions,
+            data_regions: Vec::new(),
+            known_blocks: BTreeMap::new(),
+            swept_blocks: BTreeSet::new(),
+            changed_blocks: BTreeSet::new(),
+            linear_sweep: false,
+            sweeping: false,
+            saw_illegal: false,
+            unmapped_jumps: BTreeSet::new(),
+            overlaps: BTreeSet::new(),
+            overlap_policy: OverlapPolicy::default(),
+            rejected_overlap: None,
+            exits: BTreeMap::new(),
+            function_entries: BTreeSet::new(),
+            registers: RegisterTracker::default(),
+            entry_bounds: BTreeMap::new(),
             open_blocks: Vec::new(),
             current_block: 0,
         }
     }
 
+    /// Adds regions of read-only data, which are searched for jump tables when resolving jumps through registers.
+    pub fn with_data_regions(mut self, data_regions: Vec<CodeRegion<'a>>) -> Self {
+        self.data_regions = data_regions;
+        self
+    }
+
+    /// Sets what to do about blocks that start inside an instruction that was decoded from an earlier address.
+    pub fn with_overlap_policy(mut self, overlap_policy: OverlapPolicy) -> Self {
+        self.overlap_policy = overlap_policy;
+        self
+    }
+
+    /// Enables a linear sweep of the code regions after following control flow from the entry point, which finds code
+    /// that is only reached indirectly, e.g., through function pointers. Blocks that it finds are `Confidence::Swept`.
+    pub fn with_linear_sweep(mut self, linear_sweep: bool) -> Self {
+        self.linear_sweep = linear_sweep;
+        self
+    }
+
+    /// Jumps to addresses outside of the code regions that were found by `find_blocks`.
+    pub fn unmapped_jumps(&self) -> impl Iterator<Item = &UnmappedJump> {
+        self.unmapped_jumps.iter()
+    }
+
+    /// Overlapping decodes that were found by `find_blocks`, whatever the policy.
+    pub fn overlaps(&self) -> impl Iterator<Item = &OverlappingDecode> {
+        self.overlaps.iter()
+    }
+
+    #[inline]
+    fn is_mapped(&self, addr: Address) -> bool {
+        self.regions.iter().any(|region| region.contains(addr))
+    }
+
+    // Reads a word of constant memory, which may be code or read-only data. Memory that the guest may write to, e.g.,
+    // to patch a jump table, isn't constant.
+    fn read_word(&self, addr: Address) -> Option<u32> {
+        read_constant_word(&self.regions, &self.data_regions, addr)
+    }
+
+    fn load_word(&mut self, rd: Reg, rs1: Reg, imm: u32) {
+        let (regions, data_regions) = (&self.regions, &self.data_regions);
+        self.registers.load_word(rd, rs1, imm, |addr| {
+            read_constant_word(regions, data_regions, addr)
+        });
+    }
+
     #[inline]
     fn next_instruction(&mut self) -> Result<u32, BlockFinderError> {
-        read_instruction(self.mem, self.addr)
+        read_instruction_from(&self.regions, self.addr)
             .map_err(|addr| BlockFinderError::MemoryReadFailed { addr })
     }
 
+    // Returns the address of the instruction that `addr` lies inside of when decoding from `start`, or `None` if `addr`
+    // is on an instruction boundary.
+    fn containing_instruction(&self, start: Address, addr: Address) -> Option<Address> {
+        let mut instruction = start;
+        while instruction < addr {
+            let ins = read_instruction_from(&self.regions, instruction).ok()?;
+            let instruction_size = if (ins & 3) == 3 { 4 } else { 2 };
+            if addr < instruction + instruction_size {
+                return Some(instruction);
+            }
+            instruction += instruction_size;
+        }
+        None
+    }
+
     fn start_block(&mut self, addr: Address) {
-        // Ignore addresses that are outside of the address range.
-        if addr as usize >= self.mem.len() {
+        // Record addresses that are outside of the code regions rather than trying to decode them.
+        if !self.is_mapped(addr) {
+            self.unmapped_jumps.insert(UnmappedJump {
+                source: self.addr,
+                target: addr,
+            });
+            return;
+        }
+
+        // Only add previously unknown blocks, but believe a guessed block once something is known to jump to it.
+        if self.known_blocks.contains_key(&addr) {
+            if !self.guessing() && self.swept_blocks.remove(&addr) {
+                self.changed_blocks.insert(addr);
+            }
             return;
         }
 
-        // Only add previously unknown blocks.
-        let is_unknown = self.known_blocks.iter().all(|b| b.start != addr);
-        if is_unknown {
-            // Start a new block.
-            self.known_blocks.push(Block::new(addr));
-            let index = self.known_blocks.len() - 1;
-            self.open_blocks.push(index);
-
-            // If the new block splits an existing block then terminate the existing block at the address immediately
-            // before the new block.
-            let splits_block = self
-                .known_blocks
-                .iter_mut()
-                .find(|b| b.start < addr && addr < b.end);
-            if let Some(block) = splits_block {
-                block.end = addr;
+        // If the new block splits an existing block then terminate the existing block at the address immediately
+        // before the new block. Only the closest block that starts before it is considered. That's the only candidate
+        // unless overlapping decodes have been duplicated, when a duplicate can hide the block that it overlaps, which
+        // then isn't split. That's still correct, as the whole of that block can run.
+        // The block that is currently being decoded is still open, but has been decoded as far as this instruction.
+        if let Some((&start, &end)) = self.known_blocks.range(..addr).next_back() {
+            let decoded_end = if start == self.current_block && end == OPEN_BLOCK_SENTINEL {
+                self.addr.wrapping_add(4)
+            } else {
+                end
+            };
+            if addr < decoded_end {
+                if let Some(instruction) = self.containing_instruction(start, addr) {
+                    // The sweep is only guessing, so don't report it, but don't believe it either.
+                    if self.sweeping {
+                        return;
+                    }
+
+                    // Splitting here would cut an instruction in two, so leave the existing block as it is.
+                    self.record_overlap(OverlappingDecode { addr, instruction });
+                    if self.overlap_policy != OverlapPolicy::Duplicate {
+                        return;
+                    }
+                } else {
+                    if end != OPEN_BLOCK_SENTINEL {
+                        self.known_blocks.insert(start, addr);
+                        self.changed_blocks.insert(start);
+                    }
+
+                    // The second half of a split block is as believable as the block that it was split from.
+                    if !self.swept_blocks.contains(&start) {
+                        self.start_block_with_confidence(addr, Confidence::Reachable);
+                        return;
+                    }
+                }
             }
         }
+
+        let confidence = if self.guessing() {
+            Confidence::Swept
+        } else {
+            Confidence::Reachable
+        };
+        self.start_block_with_confidence(addr, confidence);
+    }
+
+    // Whether the blocks that are found now are only guesses, because they were found by the sweep, or from a block
+    // that was.
+    fn guessing(&self) -> bool {
+        self.sweeping || self.swept_blocks.contains(&self.current_block)
+    }
+
+    fn start_block_with_confidence(&mut self, addr: Address, confidence: Confidence) {
+        self.known_blocks.insert(addr, OPEN_BLOCK_SENTINEL);
+        self.changed_blocks.insert(addr);
+        if confidence == Confidence::Swept {
+            self.swept_blocks.insert(addr);
+        }
+        self.open_blocks.push(addr);
+    }
+
+    // Drops the current block because the linear sweep found it to be implausible.
+    fn reject_swept_block(&mut self) {
+        self.known_blocks.remove(&self.current_block);
+        self.swept_blocks.remove(&self.current_block);
+    }
+
+    // Returns the end of the block that contains `addr`, if there is one.
+    fn block_covering(&self, addr: Address) -> Option<Address> {
+        self.known_blocks
+            .range(..=addr)
+            .next_back()
+            .map(|(_, &end)| end)
+            .filter(|&end| addr < end)
     }
 
     fn end_block(&mut self, addr: Address) {
-        let block = self.known_blocks.index_mut(self.current_block);
-        block.end = addr;
+        self.known_blocks.insert(self.current_block, addr);
+        self.changed_blocks.insert(self.current_block);
     }
 
-    pub fn find_blocks(&mut self, addr: Address) -> Result<Vec<Block>, BlockFinderError> {
-        self.start_block(addr);
+    fn record_overlap(&mut self, overlap: OverlappingDecode) {
+        if self.overlaps.insert(overlap)
+            && self.overlap_policy == OverlapPolicy::Reject
+            && self.rejected_overlap.is_none()
+        {
+            self.rejected_overlap = Some(overlap);
+        }
+    }
+
+    #[inline]
+    fn is_open(&self, start: Address) -> bool {
+        self.known_blocks.get(&start) == Some(&OPEN_BLOCK_SENTINEL)
+    }
+
+    fn discover(&mut self, entry_points: &[Address]) -> Result<(), BlockFinderError> {
+        if let Some(&addr) = entry_points.iter().find(|&&addr| !self.is_mapped(addr)) {
+            return Err(BlockFinderError::EntryPointUnmapped { addr });
+        }
+        for &addr in entry_points {
+            self.function_entries.insert(addr);
+
+            // An entry point is real code, even if the sweep found it first.
+            if self.swept_blocks.remove(&addr) {
+                self.changed_blocks.insert(addr);
+            }
+            self.start_block(addr);
+        }
+        self.decode_open_blocks()?;
+        if self.linear_sweep {
+            self.sweep()?;
+        }
+
+        if let Some(overlap) = self.rejected_overlap.take() {
+            return Err(BlockFinderError::OverlappingDecode {
+                addr: overlap.addr,
+                instruction: overlap.instruction,
+            });
+        }
+        Ok(())
+    }
+
+    fn decode_open_blocks(&mut self) -> Result<(), BlockFinderError> {
         while let Some(current_block) = self.open_blocks.pop() {
             self.current_block = current_block;
-            let mut block = self.known_blocks.index(self.current_block);
-            self.addr = block.start;
-            while (self.addr as usize) < self.mem.len() && block.end == OPEN_BLOCK_SENTINEL {
+            self.addr = current_block;
+            self.registers.clear();
+            if let Some(&(reg, limit)) = self.entry_bounds.get(&current_block) {
+                self.registers.set(reg, Value::Bounded { limit });
+            }
+            let is_swept = self.swept_blocks.contains(&current_block);
+            while self.is_mapped(self.addr) && self.is_open(self.current_block) {
                 let ins = self.next_instruction()?;
-                self.dispatch(ins);
                 let instruction_size = if (ins & 3) == 3 { 4 } else { 2 };
+
+                // Check if this instruction runs over the start of another block.
+                if instruction_size == 4 && self.known_blocks.contains_key(&(self.addr + 2)) {
+                    if is_swept {
+                        self.reject_swept_block();
+                        break;
+                    }
+                    self.record_overlap(OverlappingDecode {
+                        addr: self.addr + 2,
+                        instruction: self.addr,
+                    });
+                    if self.overlap_policy == OverlapPolicy::InterpretOnly {
+                        // End the block before this instruction, or drop it entirely if this is its first.
+                        if self.addr == self.current_block {
+                            self.known_blocks.remove(&self.current_block);
+                        } else {
+                            self.end_block(self.addr);
+                        }
+                        break;
+                    }
+                }
+
+                self.dispatch(ins);
+                if std::mem::take(&mut self.saw_illegal) && is_swept {
+                    self.reject_swept_block();
+                    break;
+                }
                 self.addr = self.addr.wrapping_add(instruction_size);
-                block = self.known_blocks.index(self.current_block);
+
+                // Stop if we've fallen through into the start of another block. Otherwise a block that falls through
+                // into one that was found first would carry on over it, and the two would overlap, which would leave
+                // the second without its predecessor and compiled twice. That happened before blocks were indexed by
+                // start, and the index's search for the block to split relies on it not happening.
+                if self.is_open(self.current_block) && self.known_blocks.contains_key(&self.addr) {
+                    self.end_block(self.addr);
+                }
             }
+
+            // Stop if we've run off the end of the code.
+            if self.is_open(self.current_block) {
+                self.end_block(self.addr);
+            }
+        }
+        Ok(())
+    }
+
+    // Looks for plausible blocks in the gaps between the blocks that have been found so far.
+    fn sweep(&mut self) -> Result<(), BlockFinderError> {
+        self.sweeping = true;
+        for index in 0..self.regions.len() {
+            let region = self.regions[index];
+            let mut addr = region.base;
+            while region.contains(addr) {
+                if self.block_covering(addr).is_none() && region.read_instruction(addr).is_ok() {
+                    self.start_block(addr);
+                    self.decode_open_blocks()?;
+                    if self.known_blocks.contains_key(&addr) {
+                        self.function_entries.insert(addr);
+                    }
+                }
+                addr = self.block_covering(addr).unwrap_or(addr.wrapping_add(2));
+            }
+        }
+        self.sweeping = false;
+        Ok(())
+    }
+
+    fn block(&self, start: Address, end: Address) -> Block {
+        let confidence = if self.swept_blocks.contains(&start) {
+            Confidence::Swept
+        } else {
+            Confidence::Reachable
+        };
+        Block {
+            start,
+            end,
+            confidence,
+        }
+    }
+
+    fn take_blocks(&mut self) -> Vec<Block> {
+        let blocks = self.blocks();
+        self.known_blocks.clear();
+        self.swept_blocks.clear();
+        self.changed_blocks.clear();
+        self.entry_bounds.clear();
+        blocks
+    }
+
+    /// Finds the basic blocks reachable from `entry_points`, adding them to the blocks that were found by previous
+    /// calls. Returns only the blocks that are new, or that have been split since the previous call.
+    pub fn add_entry_points(
+        &mut self,
+        entry_points: &[Address],
+    ) -> Result<Vec<Block>, BlockFinderError> {
+        self.discover(entry_points)?;
+        let changed_blocks = std::mem::take(&mut self.changed_blocks);
+        Ok(changed_blocks
+            .into_iter()
+            .filter_map(|start| {
+                let &end = self.known_blocks.get(&start)?;
+                Some(self.block(start, end))
+            })
+            .collect())
+    }
+
+    /// All of the blocks found by `add_entry_points` so far.
+    pub fn blocks(&self) -> Vec<Block> {
+        self.known_blocks
+            .iter()
+            .map(|(&start, &end)| self.block(start, end))
+            .collect()
+    }
+
+    /// The control-flow graph of all of the blocks found by `add_entry_points` so far.
+    pub fn cfg(&self) -> Cfg {
+        Cfg::new(self.blocks(), &self.exits, self.function_entries.clone())
+    }
+
+    pub fn find_blocks(&mut self, addr: Address) -> Result<Vec<Block>, BlockFinderError> {
+        self.discover(&[addr])?;
+        self.exits.clear();
+        self.function_entries.clear();
+        Ok(self.take_blocks())
+    }
+
+    /// Finds the basic blocks reachable from `addr` along with the control flow between them.
+    pub fn find_cfg(&mut self, addr: Address) -> Result<Cfg, BlockFinderError> {
+        self.discover(&[addr])?;
+        let exits = std::mem::take(&mut self.exits);
+        let function_entries = std::mem::take(&mut self.function_entries);
+        Ok(Cfg::new(self.take_blocks(), &exits, function_entries))
+    }
+
+    #[inline]
+    fn add_exit(&mut self, target: Option<Address>, kind: EdgeKind) {
+        // An instruction is decoded more than once if it's in overlapping blocks.
+        let exits = self.exits.entry(self.addr).or_default();
+        if !exits.contains(&(target, kind)) {
+            exits.push((target, kind));
         }
-        self.known_blocks.sort_unstable();
-        Ok(std::mem::take(&mut self.known_blocks))
     }
 
     fn conditional_jump(&mut self, branch_taken: Address, branch_not_taken: Address) {
+        self.add_exit(Some(branch_taken), EdgeKind::Taken);
+        self.add_exit(Some(branch_not_taken), EdgeKind::NotTaken);
         self.end_block(branch_not_taken);
         self.start_block(branch_not_taken);
         self.start_block(branch_taken);
     }
 
-    fn direct_jump(&mut self, next_instruction: Address, target: Address) {
+    fn direct_jump(&mut self, next_instruction: Address, target: Address, kind: EdgeKind) {
+        self.add_exit(Some(target), kind);
         self.end_block(next_instruction);
         self.start_block(target);
     }
 
-    fn new_block(&mut self, next_instruction: Address) {
+    // A call is a jump that is expected to return to the next instruction, so that's where the caller resumes.
+    fn call(&mut self, next_instruction: Address, target: Address) {
+        self.add_exit(Some(target), EdgeKind::Call);
+        self.add_exit(Some(next_instruction), EdgeKind::Fallthrough);
+        self.end_block(next_instruction);
+        if self.is_mapped(target) {
+            self.function_entries.insert(target);
+        }
+        self.start_block(target);
+        self.start_block(next_instruction);
+    }
+
+    fn indirect_call(&mut self, next_instruction: Address) {
+        self.add_exit(None, EdgeKind::Indirect);
+        self.add_exit(Some(next_instruction), EdgeKind::Fallthrough);
+        self.end_block(next_instruction);
+        self.start_block(next_instruction);
+    }
+
+    fn indirect_jump(&mut self, next_instruction: Address, kind: EdgeKind) {
+        self.add_exit(None, kind);
+        self.end_block(next_instruction);
+        self.start_block(next_instruction);
+    }
+
+    // Reads code addresses from a jump table until something that isn't one is found, or, if the table is known to be
+    // `limit` bytes long, until the end of the table.
+    fn read_jump_table(&self, base: Address, limit: Option<u32>) -> Vec<Address> {
+        let entries = limit.map_or(MAX_JUMP_TABLE_ENTRIES as u32, |limit| {
+            limit.div_ceil(4).min(MAX_JUMP_TABLE_ENTRIES as u32)
+        });
+        (0..entries)
+            .map_while(|i| self.read_word(base.wrapping_add(i * 4)))
+            .take_while(|&target| target & 1 == 0 && self.is_mapped(target))
+            .collect()
+    }
+
+    // Records that `reg` is below `limit` on entry to the block at `start`, which a branch is about to start, e.g., for
+    // the bounds check before a jump through a table. It's assumed that the branch is the only way into the block, as
+    // it is for a bounds check, so the block must be new.
+    fn bound_on_entry(&mut self, start: Address, reg: Reg, limit: u32) {
+        if reg != Reg::ZERO && !self.known_blocks.contains_key(&start) {
+            self.entry_bounds.insert(start, (reg, limit));
+        }
+    }
+
+    // The bounds that an unsigned comparison of `rs1 < rs2` puts on the blocks that it branches to, if one side of it
+    // is a constant, as `(if true, if false)`.
+    fn unsigned_bounds(&self, rs1: Reg, rs2: Reg) -> (Option<Bound>, Option<Bound>) {
+        match (self.registers.get(rs1), self.registers.get(rs2)) {
+            (_, Value::Const(limit)) => (Some((rs1, limit)), None),
+            (Value::Const(value), _) => (None, value.checked_add(1).map(|limit| (rs2, limit))),
+            _ => (None, None),
+        }
+    }
+
+    // Tries to resolve a jump through `rs1` from the values of the registers in the current block. Returns false if
+    // the target is unknown, in which case the caller should treat it as an indirect jump.
+    fn resolve_register_jump(
+        &mut self,
+        next_instruction: Address,
+        rs1: Reg,
+        imm: u32,
+        is_call: bool,
+    ) -> bool {
+        // The targets in a table whose size isn't known may be past its end, so they're only guesses.
+        let (targets, guessed): (Vec<Address>, bool) = match self.registers.get(rs1) {
+            Value::Const(target) => (vec![target.wrapping_add(imm) & !1], false),
+            Value::TableEntry { base, limit } => (
+                self.read_jump_table(base, limit)
+                    .into_iter()
+                    .map(|target| target.wrapping_add(imm) & !1)
+                    .collect(),
+                limit.is_none(),
+            ),
+            _ => (Vec::new(), false),
+        };
+        match (targets.as_slice(), is_call) {
+            ([], _) => return false,
+            (&[target], true) => self.call(next_instruction, target),
+            (&[target], false) => self.direct_jump(next_instruction, target, EdgeKind::Taken),
+            (_, true) => {
+                // e.g., a call through a table of function pointers.
+                for &target in &targets {
+                    self.add_exit(Some(target), EdgeKind::Call);
+                    if self.is_mapped(target) {
+                        self.function_entries.insert(target);
+                    }
+                }
+                self.add_exit(Some(next_instruction), EdgeKind::Fallthrough);
+                self.end_block(next_instruction);
+                self.start_table_blocks(&targets, guessed);
+                self.start_block(next_instruction);
+            }
+            (_, false) => {
+                // e.g., a switch statement.
+                for &target in &targets {
+                    self.add_exit(Some(target), EdgeKind::Indirect);
+                }
+                self.end_block(next_instruction);
+                self.start_table_blocks(&targets, guessed);
+                self.start_block(next_instruction);
+            }
+        }
+        true
+    }
+
+    // Starts blocks at the targets read from a jump table. If they're only guesses, then they're treated like blocks
+    // found by the sweep, which are dropped if they don't decode.
+    fn start_table_blocks(&mut self, targets: &[Address], guessed: bool) {
+        let sweeping = self.sweeping;
+        self.sweeping = sweeping || guessed;
+        for &target in targets {
+            self.start_block(target);
+        }
+        self.sweeping = sweeping;
+    }
+
+    fn trap(&mut self, next_instruction: Address) {
+        self.add_exit(None, EdgeKind::Trap);
+        self.add_exit(Some(next_instruction), EdgeKind::Fallthrough);
         self.end_block(next_instruction);
         self.start_block(next_instruction);
     }
@@ -120,7 +712,9 @@ impl<'a> BlockFinder<'a> {
 impl HandleRv32i for BlockFinder<'_> {
     type Item = ();
 
-    fn illegal(&mut self, _ins: u32) -> Self::Item {}
+    fn illegal(&mut self, _ins: u32) -> Self::Item {
+        self.saw_illegal = true;
+    }
 
     fn beq(
         &mut self,
@@ -160,117 +754,151 @@ impl HandleRv32i for BlockFinder<'_> {
 
     fn bltu(
         &mut self,
-        _rs1: arviss::decoding::Reg,
-        _rs2: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        rs2: arviss::decoding::Reg,
         bimm: u32,
     ) -> Self::Item {
-        self.conditional_jump(self.addr.wrapping_add(bimm), self.addr + 4);
+        let (taken, not_taken) = (self.addr.wrapping_add(bimm), self.addr + 4);
+        let (if_less, if_not_less) = self.unsigned_bounds(rs1, rs2);
+        for (start, bound) in [(taken, if_less), (not_taken, if_not_less)] {
+            if let Some((reg, limit)) = bound {
+                self.bound_on_entry(start, reg, limit);
+            }
+        }
+        self.conditional_jump(taken, not_taken);
     }
 
     fn bgeu(
         &mut self,
-        _rs1: arviss::decoding::Reg,
-        _rs2: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        rs2: arviss::decoding::Reg,
         bimm: u32,
     ) -> Self::Item {
-        self.conditional_jump(self.addr.wrapping_add(bimm), self.addr + 4);
+        let (taken, not_taken) = (self.addr.wrapping_add(bimm), self.addr + 4);
+        let (if_less, if_not_less) = self.unsigned_bounds(rs1, rs2);
+        for (start, bound) in [(not_taken, if_less), (taken, if_not_less)] {
+            if let Some((reg, limit)) = bound {
+                self.bound_on_entry(start, reg, limit);
+            }
+        }
+        self.conditional_jump(taken, not_taken);
     }
 
     fn lb(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn lh(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn lw(
         &mut self,
-        _rd: arviss::decoding::Reg,
-        _rs1: arviss::decoding::Reg,
-        _iimm: u32,
+        rd: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        iimm: u32,
     ) -> Self::Item {
+        self.load_word(rd, rs1, iimm);
     }
 
     fn lbu(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn lhu(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn addi(
         &mut self,
-        _rd: arviss::decoding::Reg,
-        _rs1: arviss::decoding::Reg,
-        _iimm: u32,
+        rd: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        iimm: u32,
     ) -> Self::Item {
+        self.registers.add_immediate(rd, rs1, iimm);
     }
 
     fn slti(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn sltiu(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn xori(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn ori(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn andi(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _iimm: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn jalr(
         &mut self,
-        _rd: arviss::decoding::Reg,
-        _rs1: arviss::decoding::Reg,
-        _iimm: u32,
+        rd: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        iimm: u32,
     ) -> Self::Item {
-        self.new_block(self.addr + 4);
+        if self.resolve_register_jump(self.addr + 4, rs1, iimm, is_link_register(rd)) {
+            return;
+        }
+        if is_link_register(rd) {
+            self.indirect_call(self.addr + 4);
+        } else if rd == Reg::ZERO && is_link_register(rs1) && iimm == 0 {
+            self.indirect_jump(self.addr + 4, EdgeKind::Return);
+        } else {
+            self.indirect_jump(self.addr + 4, EdgeKind::Indirect);
+        }
     }
 
     fn sb(
@@ -297,116 +925,139 @@ impl HandleRv32i for BlockFinder<'_> {
     ) -> Self::Item {
     }
 
-    fn auipc(&mut self, _rd: arviss::decoding::Reg, _uimm: u32) -> Self::Item {}
+    fn auipc(&mut self, rd: arviss::decoding::Reg, uimm: u32) -> Self::Item {
+        self.registers
+            .set(rd, Value::Const(self.addr.wrapping_add(uimm)));
+    }
 
-    fn lui(&mut self, _rd: arviss::decoding::Reg, _uimm: u32) -> Self::Item {}
+    fn lui(&mut self, rd: arviss::decoding::Reg, uimm: u32) -> Self::Item {
+        self.registers.set(rd, Value::Const(uimm));
+    }
 
-    fn jal(&mut self, _rd: arviss::decoding::Reg, jimm: u32) -> Self::Item {
-        self.direct_jump(self.addr + 4, self.addr.wrapping_add(jimm));
+    fn jal(&mut self, rd: arviss::decoding::Reg, jimm: u32) -> Self::Item {
+        let target = self.addr.wrapping_add(jimm);
+        if is_link_register(rd) {
+            self.call(self.addr + 4, target);
+        } else {
+            self.direct_jump(self.addr + 4, target, EdgeKind::Taken);
+        }
     }
 
     fn add(
         &mut self,
-        _rd: arviss::decoding::Reg,
-        _rs1: arviss::decoding::Reg,
-        _rs2: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.add(rd, rs1, rs2);
     }
 
     fn sub(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn sll(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn slt(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn sltu(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn xor(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn srl(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn sra(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn or(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn and(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _rs2: arviss::decoding::Reg,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn slli(
         &mut self,
-        _rd: arviss::decoding::Reg,
-        _rs1: arviss::decoding::Reg,
-        _shamt: u32,
+        rd: arviss::decoding::Reg,
+        rs1: arviss::decoding::Reg,
+        shamt: u32,
     ) -> Self::Item {
+        self.registers.shift_left(rd, rs1, shamt);
     }
 
     fn srli(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _shamt: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn srai(
         &mut self,
-        _rd: arviss::decoding::Reg,
+        rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
         _shamt: u32,
     ) -> Self::Item {
+        self.registers.clobber(rd);
     }
 
     fn fence(
@@ -415,28 +1066,41 @@ impl HandleRv32i for BlockFinder<'_> {
         _rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
     ) -> Self::Item {
+        // A `fence.i` ends the block, so that the code after it can be checked for changes before it runs. The decoder
+        // doesn't tell it apart from `fence`, so look at funct3.
+        let is_fence_i =
+            read_instruction_from(&self.regions, self.addr).is_ok_and(|ins| (ins >> 12) & 7 == 1);
+        if is_fence_i {
+            let next_instruction = self.addr + 4;
+            self.add_exit(Some(next_instruction), EdgeKind::Fallthrough);
+            self.end_block(next_instruction);
+            self.start_block(next_instruction);
+        }
     }
 
     fn ecall(&mut self) -> Self::Item {
-        self.new_block(self.addr + 4);
+        self.trap(self.addr + 4);
     }
 
     fn ebreak(&mut self) -> Self::Item {
-        self.new_block(self.addr + 4);
+        self.trap(self.addr + 4);
     }
 }
 
 impl HandleRv32c for BlockFinder<'_> {
     type Item = ();
 
-    fn c_addi4spn(&mut self, _rdp: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_addi4spn(&mut self, rdp: arviss::decoding::Reg, imm: u32) -> Self::Item {
+        self.registers.add_immediate(rdp, Reg::SP, imm);
+    }
 
     fn c_lw(
         &mut self,
-        _rdp: arviss::decoding::Reg,
-        _rs1p: arviss::decoding::Reg,
-        _imm: u32,
+        rdp: arviss::decoding::Reg,
+        rs1p: arviss::decoding::Reg,
+        imm: u32,
     ) -> Self::Item {
+        self.load_word(rdp, rs1p, imm);
     }
 
     fn c_sw(
@@ -447,44 +1111,46 @@ impl HandleRv32c for BlockFinder<'_> {
     ) -> Self::Item {
     }
 
-    fn c_sub(
-        &mut self,
-        _rdrs1p: arviss::decoding::Reg,
-        _rs2p: arviss::decoding::Reg,
-    ) -> Self::Item {
+    fn c_sub(&mut self, rdrs1p: arviss::decoding::Reg, _rs2p: arviss::decoding::Reg) -> Self::Item {
+        self.registers.clobber(rdrs1p);
     }
 
-    fn c_xor(
-        &mut self,
-        _rdrs1p: arviss::decoding::Reg,
-        _rs2p: arviss::decoding::Reg,
-    ) -> Self::Item {
+    fn c_xor(&mut self, rdrs1p: arviss::decoding::Reg, _rs2p: arviss::decoding::Reg) -> Self::Item {
+        self.registers.clobber(rdrs1p);
     }
 
-    fn c_or(&mut self, _rdrs1p: arviss::decoding::Reg, _rs2p: arviss::decoding::Reg) -> Self::Item {
+    fn c_or(&mut self, rdrs1p: arviss::decoding::Reg, _rs2p: arviss::decoding::Reg) -> Self::Item {
+        self.registers.clobber(rdrs1p);
     }
 
-    fn c_and(
-        &mut self,
-        _rdrs1p: arviss::decoding::Reg,
-        _rs2p: arviss::decoding::Reg,
-    ) -> Self::Item {
+    fn c_and(&mut self, rdrs1p: arviss::decoding::Reg, _rs2p: arviss::decoding::Reg) -> Self::Item {
+        self.registers.clobber(rdrs1p);
     }
 
     fn c_nop(&mut self, _imm: u32) -> Self::Item {}
 
-    fn c_addi16sp(&mut self, _imm: u32) -> Self::Item {}
+    fn c_addi16sp(&mut self, imm: u32) -> Self::Item {
+        self.registers.add_immediate(Reg::SP, Reg::SP, imm);
+    }
 
-    fn c_andi(&mut self, _rsrs1p: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_andi(&mut self, rsrs1p: arviss::decoding::Reg, _imm: u32) -> Self::Item {
+        self.registers.clobber(rsrs1p);
+    }
 
-    fn c_addi(&mut self, _rdrs1n0: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_addi(&mut self, rdrs1n0: arviss::decoding::Reg, imm: u32) -> Self::Item {
+        self.registers.add_immediate(rdrs1n0, rdrs1n0, imm);
+    }
 
-    fn c_li(&mut self, _rd: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_li(&mut self, rd: arviss::decoding::Reg, imm: u32) -> Self::Item {
+        self.registers.add_immediate(rd, Reg::ZERO, imm);
+    }
 
-    fn c_lui(&mut self, _rdn2: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_lui(&mut self, rdn2: arviss::decoding::Reg, imm: u32) -> Self::Item {
+        self.registers.set(rdn2, Value::Const(imm));
+    }
 
     fn c_j(&mut self, imm: u32) -> Self::Item {
-        self.direct_jump(self.addr + 2, self.addr.wrapping_add(imm));
+        self.direct_jump(self.addr + 2, self.addr.wrapping_add(imm), EdgeKind::Taken);
     }
 
     fn c_beqz(&mut self, _rs1p: arviss::decoding::Reg, imm: u32) -> Self::Item {
@@ -495,38 +1161,55 @@ impl HandleRv32c for BlockFinder<'_> {
         self.conditional_jump(self.addr.wrapping_add(imm), self.addr + 2);
     }
 
-    fn c_jr(&mut self, _rs1n0: arviss::decoding::Reg) -> Self::Item {
-        self.new_block(self.addr + 2);
+    fn c_jr(&mut self, rs1n0: arviss::decoding::Reg) -> Self::Item {
+        if self.resolve_register_jump(self.addr + 2, rs1n0, 0, false) {
+            return;
+        }
+        let kind = if is_link_register(rs1n0) {
+            EdgeKind::Return
+        } else {
+            EdgeKind::Indirect
+        };
+        self.indirect_jump(self.addr + 2, kind);
     }
 
-    fn c_jalr(&mut self, _rs1n0: arviss::decoding::Reg) -> Self::Item {
-        self.new_block(self.addr + 2);
+    fn c_jalr(&mut self, rs1n0: arviss::decoding::Reg) -> Self::Item {
+        if !self.resolve_register_jump(self.addr + 2, rs1n0, 0, true) {
+            self.indirect_call(self.addr + 2);
+        }
     }
 
     fn c_ebreak(&mut self) -> Self::Item {
-        self.new_block(self.addr + 2);
+        self.trap(self.addr + 2);
     }
 
-    fn c_mv(&mut self, _rd: arviss::decoding::Reg, _rs2n0: arviss::decoding::Reg) -> Self::Item {}
+    fn c_mv(&mut self, rd: arviss::decoding::Reg, rs2n0: arviss::decoding::Reg) -> Self::Item {
+        self.registers.add_immediate(rd, rs2n0, 0);
+    }
 
-    fn c_add(
-        &mut self,
-        _rdrs1: arviss::decoding::Reg,
-        _rs2n0: arviss::decoding::Reg,
-    ) -> Self::Item {
+    fn c_add(&mut self, rdrs1: arviss::decoding::Reg, rs2n0: arviss::decoding::Reg) -> Self::Item {
+        self.registers.add(rdrs1, rdrs1, rs2n0);
     }
 
-    fn c_lwsp(&mut self, _rdn0: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_lwsp(&mut self, rdn0: arviss::decoding::Reg, _imm: u32) -> Self::Item {
+        self.registers.clobber(rdn0);
+    }
 
     fn c_swsp(&mut self, _rs2: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
 
     fn c_jal(&mut self, imm: u32) -> Self::Item {
-        self.direct_jump(self.addr + 2, self.addr.wrapping_add(imm));
+        self.call(self.addr + 2, self.addr.wrapping_add(imm));
     }
 
-    fn c_srli(&mut self, _rdrs1p: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_srli(&mut self, rdrs1p: arviss::decoding::Reg, _imm: u32) -> Self::Item {
+        self.registers.clobber(rdrs1p);
+    }
 
-    fn c_srai(&mut self, _rdrs1p: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_srai(&mut self, rdrs1p: arviss::decoding::Reg, _imm: u32) -> Self::Item {
+        self.registers.clobber(rdrs1p);
+    }
 
-    fn c_slli(&mut self, _rdrs1n0: arviss::decoding::Reg, _imm: u32) -> Self::Item {}
+    fn c_slli(&mut self, rdrs1n0: arviss::decoding::Reg, imm: u32) -> Self::Item {
+        self.registers.shift_left(rdrs1n0, rdrs1n0, imm);
+    }
 }
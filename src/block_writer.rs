@@ -1,6 +1,8 @@
 use crate::block_finder::*;
-use crate::read_instruction::*;
+use crate::code_watch::{is_store, CODE_FLUSH, NO_CODE_CHANGE};
+use arviss::decoding::Reg;
 use arviss::{disassembler::Disassembler, Address, DispatchRv32ic, HandleRv32c, HandleRv32i};
+use std::collections::BTreeMap;
 use std::io::Write;
 use thiserror::Error;
 
@@ -16,36 +18,246 @@ pub enum BlockWriterError {
     },
 }
 
+/// The runtime that generated blocks are written against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Runtime {
+    /// Blocks take an arviss `Rv32iCpu<BasicMem>` and must be linked against the arviss rlib.
+    #[default]
+    Arviss,
+
+    /// Blocks take a `RegisterFile` and a `HostApi` from `dll_api` and have no dependencies, so they compile with
+    /// plain rustc.
+    SelfContained,
+}
+
+/// Identifies the code that `BlockWriter` generates. Bump it whenever that changes, so that cached libraries written by
+/// an older version aren't used.
+pub const BLOCK_WRITER_VERSION: u32 = 7;
+
+// When a chained block or a superblock has to give control back to the host rather than carry on to the next block.
+// The budget is only checked between blocks, so a run can go over it by up to one block.
+const SHOULD_RETURN: &str = "cpu.is_trapped() || code_changed() || budget.retired >= budget.limit";
+
+const NATIVE_ABI: &str = include_str!("native_abi.rs");
+const NATIVE_RUNTIME: &str = include_str!("native_runtime.rs");
+
 pub struct BlockWriter<'a> {
-    mem: &'a [u8],
+    regions: Vec<CodeRegion<'a>>,
     dis: Disassembler,
     pc: Address,
     is_jump: bool,
+    targets: Vec<Address>, // Where the block being written can go next, if it's known statically.
+    next_pc: Address,      // The address of the instruction after the one being written.
+    runtime: Runtime,
 }
 
 impl<'a> BlockWriter<'a> {
     pub fn new(mem: &'a [u8]) -> Self {
+        Self::with_runtime(mem, Runtime::Arviss)
+    }
+
+    pub fn with_runtime(mem: &'a [u8], runtime: Runtime) -> Self {
+        Self::with_regions(vec![CodeRegion::new(0, mem)], runtime)
+    }
+
+    /// Creates a block writer for code that is mapped into one or more regions of the address space.
+    pub fn with_regions(regions: Vec<CodeRegion<'a>>, runtime: Runtime) -> Self {
         Self {
-            mem,
+            regions,
             dis: Disassembler,
             pc: 0,
             is_jump: false,
+            targets: Vec::new(),
+            next_pc: 0,
+            runtime,
         }
     }
 
     pub fn begin(&mut self, writer: &mut impl Write) -> Result<(), BlockWriterError> {
-        writeln!(writer, "#![no_std]")?;
-        writeln!(writer, "use arviss::HandleRv32i;")?;
-        writeln!(writer, "use arviss::platforms::basic::*;")?;
-        writeln!(writer, "use arviss::decoding::Reg;")?;
-        writeln!(writer, "type Cpu = Rv32iCpu::<BasicMem>;")?;
+        match self.runtime {
+            Runtime::Arviss => {
+                writeln!(writer, "#![no_std]")?;
+                writeln!(writer, "use arviss::HandleRv32i;")?;
+                writeln!(writer, "use arviss::platforms::basic::*;")?;
+                writeln!(writer, "use arviss::decoding::Reg;")?;
+                writeln!(writer, "type Cpu = Rv32iCpu::<BasicMem>;")?;
+                writeln!(
+                    writer,
+                    "pub type ChainedFunc = extern \"C\" fn(&mut Cpu, &mut Budget) -> NextFunc;"
+                )?;
+                writeln!(writer, "#[repr(transparent)]")?;
+                writeln!(writer, "pub struct NextFunc(pub Option<ChainedFunc>);")?;
+                writeln!(writer, "#[repr(C)]")?;
+                writeln!(
+                    writer,
+                    "pub struct Budget {{ pub retired: u64, pub limit: u64 }}"
+                )?;
+                self.write_code_watch(writer)?;
+            }
+            Runtime::SelfContained => {
+                writeln!(writer, "#![allow(dead_code)]")?;
+                writeln!(writer, "{NATIVE_ABI}")?;
+                writeln!(writer, "{NATIVE_RUNTIME}")?;
+
+                // Self-contained blocks don't report writes to code.
+                writeln!(
+                    writer,
+                    "fn is_code(_addr: u32, _len: u32) -> bool {{ false }}"
+                )?;
+                writeln!(writer, "fn note_code_write(_addr: u32, _len: u32) {{}}")?;
+                writeln!(writer, "fn note_flush() {{}}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes the functions that blocks use to report writes to code and `fence.i`, and that the host uses to collect
+    // the reports. See `CodeChange` for the encoding.
+    fn write_code_watch(&self, writer: &mut impl Write) -> Result<(), BlockWriterError> {
+        let code_ranges: Vec<String> = self
+            .regions
+            .iter()
+            .filter(|region| !region.mem.is_empty())
+            .map(|region| {
+                let last = region.base.wrapping_add(region.mem.len() as u32 - 1);
+                format!("0x{:08x}..=0x{:08x}", region.base, last)
+            })
+            .collect();
+        let in_code = match code_ranges.is_empty() {
+            true => "{ let _ = addr; false }".to_string(),
+            false => format!("{{ matches!(addr, {}) }}", code_ranges.join(" | ")),
+        };
+        write!(
+            writer,
+            r#"
+use core::sync::atomic::{{AtomicU64, Ordering}};
+static CODE_CHANGE: AtomicU64 = AtomicU64::new({NO_CODE_CHANGE});
+#[no_mangle]
+pub extern "C" fn take_code_change() -> u64 {{ CODE_CHANGE.swap({NO_CODE_CHANGE}, Ordering::Relaxed) }}
+#[allow(dead_code)]
+fn code_changed() -> bool {{ CODE_CHANGE.load(Ordering::Relaxed) != {NO_CODE_CHANGE} }}
+#[allow(dead_code)]
+fn note_code_write(addr: u32, len: u32) {{ CODE_CHANGE.store(((len as u64) << 32) | addr as u64, Ordering::Relaxed); }}
+#[allow(dead_code)]
+fn note_flush() {{ CODE_CHANGE.store({CODE_FLUSH}, Ordering::Relaxed); }}
+#[allow(dead_code)]
+fn in_code(addr: u32) -> bool {in_code}
+#[allow(dead_code)]
+fn is_code(addr: u32, len: u32) -> bool {{ in_code(addr) || in_code(addr.wrapping_add(len - 1)) }}
+"#
+        )?;
 
         Ok(())
     }
 
+    // Writes the switches that the host uses to stop chained blocks and superblocks from going to blocks that it has
+    // dropped, e.g., because they're stale. The code for a dropped block stays in the library while other blocks are
+    // still using it, so without them, blocks that were chained to it would carry on running it.
+    fn write_block_switches(
+        &self,
+        writer: &mut impl Write,
+        blocks: &BTreeMap<Address, Block>,
+    ) -> Result<(), BlockWriterError> {
+        let indices: Vec<String> = blocks
+            .keys()
+            .enumerate()
+            .map(|(index, start)| format!("0x{start:08x} => Some({index}),"))
+            .collect();
+        let count = blocks.len();
+        write!(
+            writer,
+            r#"
+use core::sync::atomic::AtomicBool;
+static ENABLED: [AtomicBool; {count}] = [const {{ AtomicBool::new(true) }}; {count}];
+#[inline(always)]
+fn block_index(addr: u32) -> Option<usize> {{ match addr {{ {} _ => None }} }}
+#[allow(dead_code)]
+#[inline(always)]
+fn is_enabled(addr: u32) -> bool {{ block_index(addr).is_some_and(|index| ENABLED[index].load(Ordering::Relaxed)) }}
+#[no_mangle]
+pub extern "C" fn disable_block(addr: u32) {{
+    if let Some(index) = block_index(addr) {{ ENABLED[index].store(false, Ordering::Relaxed); }}
+}}
+"#,
+            indices.join(" ")
+        )?;
+
+        Ok(())
+    }
+
+    // Writes a store of `len` bytes. A store to code ends the block early, so that the host can drop any compiled
+    // blocks that it made stale before running them.
+    fn store(&self, write: &str, rs1: Reg, value: &str, imm: u32, len: u32) -> String {
+        format!(
+            r#"
+            let store_addr = cpu.rx({rs1}).wrapping_add({imm});
+            if let Err(address) = cpu.{write}(store_addr, {value}) {{
+                cpu.handle_trap(TrapCause::StoreAccessFault(address));
+                {}
+            }} else if is_code(store_addr, {len}) {{
+                note_code_write(store_addr, {len});
+                cpu.set_next_pc(0x{:08x});
+                return;
+            }}
+        "#,
+            self.trap_exit(),
+            self.next_pc
+        )
+    }
+
+    // Writes an expression for the number of instructions that `block` retired, given the pc in `next` that it left
+    // for. That's all of them, unless it left early: after a trap, `next` is the instruction that trapped, and after a
+    // store wrote to code, it's the one after the store.
+    fn retired(&self, block: &Block, next: &str) -> String {
+        let all = count_instructions(&self.regions, block.start, block.end);
+        let mut trap_exits = Vec::new();
+        let mut store_exits = Vec::new();
+        let mut count = 0;
+        let mut addr = block.start;
+        while let Ok(ins) = self.instruction_at(addr) {
+            let start = addr;
+            addr += if (ins & 3) == 3 { 4 } else { 2 };
+            count += 1;
+            if addr >= block.end {
+                break;
+            }
+            trap_exits.push(format!("0x{start:08x} => {count},"));
+            if is_store(ins) {
+                store_exits.push(format!("0x{addr:08x} => {count},"));
+            }
+        }
+        let mut retired = all.to_string();
+        if !store_exits.is_empty() {
+            retired = format!(
+                "if code_changed() {{ match {next} {{ {} _ => {all} }} }} else {{ {retired} }}",
+                store_exits.join(" ")
+            );
+        }
+        if !trap_exits.is_empty() {
+            retired = format!(
+                "if cpu.is_trapped() {{ match {next} {{ {} _ => {all} }} }} else {{ {retired} }}",
+                trap_exits.join(" ")
+            );
+        }
+        retired
+    }
+
+    // Leaves the block once the instruction being written has trapped, with the pc still on it, like the interpreter.
+    fn trap_exit(&self) -> String {
+        format!("cpu.set_next_pc(0x{:08x}); return;", self.pc)
+    }
+
+    // Records a statically known target of the jump being written, and returns it.
+    fn jump_target(&mut self, target: Address) -> Address {
+        self.targets.push(target);
+        target
+    }
+
     #[inline]
     fn instruction_at(&self, addr: Address) -> Result<u32, BlockWriterError> {
-        read_instruction(self.mem, addr).map_err(|addr| BlockWriterError::ReadFailed { addr })
+        read_instruction_from(&self.regions, addr)
+            .map_err(|addr| BlockWriterError::ReadFailed { addr })
     }
 
     pub fn write_block(
@@ -54,12 +266,25 @@ impl<'a> BlockWriter<'a> {
         block: &Block,
     ) -> Result<(), BlockWriterError> {
         let mut addr = block.start;
+        self.targets.clear();
         writeln!(writer, "\n#[no_mangle]")?;
-        writeln!(
-            writer,
-            "pub extern \"C\" fn block_{:08x}_{:08x}(cpu: &mut Cpu) {{",
-            block.start, block.end
-        )?;
+        match self.runtime {
+            Runtime::Arviss => {
+                writeln!(
+                    writer,
+                    "pub extern \"C\" fn block_{:08x}_{:08x}(cpu: &mut Cpu) {{",
+                    block.start, block.end
+                )?;
+            }
+            Runtime::SelfContained => {
+                writeln!(
+                    writer,
+                    "pub extern \"C\" fn block_{:08x}_{:08x}(regs: &mut RegisterFile, host: &HostApi) {{",
+                    block.start, block.end
+                )?;
+                writeln!(writer, "let cpu = &mut Cpu::new(regs, host);")?;
+            }
+        }
         while addr < block.end {
             self.is_jump = false;
             self.pc = addr;
@@ -77,6 +302,7 @@ impl<'a> BlockWriter<'a> {
                 writeln!(writer, "// {:08x} {:08x} {}", addr, ins, code)?;
                 addr += 4;
             }
+            self.next_pc = addr;
             let code = self.dispatch(ins);
             writeln!(writer, "{code}")?;
 
@@ -87,6 +313,7 @@ impl<'a> BlockWriter<'a> {
             if addr >= block.end && !self.is_jump {
                 // We only do this for non-jumps, because jumps do it themselves.
                 writeln!(writer, "cpu.set_next_pc(0x{addr:08x});")?;
+                self.targets.push(addr);
             }
         }
         writeln!(writer, "}}")?;
@@ -94,15 +321,132 @@ impl<'a> BlockWriter<'a> {
         Ok(())
     }
 
+    /// Writes a function that runs the block and adds its instructions to the budget, then returns the function for the
+    /// block that comes next, so that the caller can go straight to it. That's only possible for targets that are known
+    /// statically and are among `blocks`. Otherwise, or if the CPU trapped or the budget ran out, it returns
+    /// `NextFunc(None)` and the caller has to look up `cpu.pc()`. It also returns `NextFunc(None)` for a block that the
+    /// host has disabled with `disable_block`. Must follow `write_block` for the same block.
+    pub fn write_chained_block(
+        &mut self,
+        writer: &mut impl Write,
+        block: &Block,
+        blocks: &BTreeMap<Address, Block>,
+    ) -> Result<(), BlockWriterError> {
+        let name = format!("{:08x}_{:08x}", block.start, block.end);
+        writeln!(writer, "\n#[no_mangle]")?;
+        writeln!(
+            writer,
+            "pub extern \"C\" fn chain_{name}(cpu: &mut Cpu, budget: &mut Budget) -> NextFunc {{"
+        )?;
+        writeln!(writer, "block_{name}(cpu);")?;
+        writeln!(writer, "let next = cpu.transfer();")?;
+        writeln!(writer, "budget.retired += {};", self.retired(block, "next"))?;
+        writeln!(writer, "if {SHOULD_RETURN} {{ return NextFunc(None); }}")?;
+        writeln!(writer, "match next {{")?;
+        let mut targets = self.targets.clone();
+        targets.sort_unstable();
+        targets.dedup();
+        for target in targets {
+            if let Some(next) = blocks.get(&target) {
+                writeln!(
+                    writer,
+                    "0x{:08x} if is_enabled(0x{:08x}) => NextFunc(Some(chain_{:08x}_{:08x})),",
+                    target, target, next.start, next.end
+                )?;
+            }
+        }
+        writeln!(writer, "_ => NextFunc(None),")?;
+        writeln!(writer, "}}")?;
+        writeln!(writer, "}}")?;
+
+        Ok(())
+    }
+
     pub fn write_blocks(
         &mut self,
         writer: &mut impl Write,
         blocks: impl IntoIterator<Item = &'a Block>,
     ) -> Result<(), BlockWriterError> {
         self.begin(writer)?;
+        let blocks: BTreeMap<Address, Block> = blocks
+            .into_iter()
+            .map(|block| (block.start, *block))
+            .collect();
+        if self.runtime == Runtime::Arviss {
+            self.write_block_switches(writer, &blocks)?;
+        }
+        for block in blocks.values() {
+            self.write_block(writer, block)?;
+            if self.runtime == Runtime::Arviss {
+                self.write_chained_block(writer, block, &blocks)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a superblock: a function that runs any of `blocks`, starting with the one at `cpu.pc()`, in a loop until
+    /// control leaves them, the CPU traps or the budget runs out. As rustc sees the loops, it can optimize across the
+    /// blocks. Like a chained block, it returns with the next pc transferred, but it always returns `NextFunc(None)`.
+    /// It leaves them for a block that the host has disabled, too. It's named after the first block, and the blocks and
+    /// their switches must already have been written.
+    pub fn write_superblock(
+        &mut self,
+        writer: &mut impl Write,
+        blocks: &[Block],
+    ) -> Result<(), BlockWriterError> {
+        let Some(entry) = blocks.first() else {
+            return Ok(());
+        };
+        writeln!(writer, "\n#[no_mangle]")?;
+        writeln!(
+            writer,
+            "pub extern \"C\" fn superblock_{:08x}(cpu: &mut Cpu, budget: &mut Budget) -> NextFunc {{",
+            entry.start
+        )?;
+        writeln!(writer, "let mut pc = cpu.pc();")?;
+        writeln!(writer, "loop {{")?;
+        writeln!(writer, "pc = match pc {{")?;
         for block in blocks {
+            let (start, end) = (block.start, block.end);
+            let retired = self.retired(block, "next");
+            writeln!(
+                writer,
+                "0x{start:08x} if is_enabled(0x{start:08x}) => {{ \
+                block_{start:08x}_{end:08x}(cpu); let next = cpu.transfer(); budget.retired += {retired}; next }}",
+            )?;
+        }
+        writeln!(writer, "_ => return NextFunc(None),")?;
+        writeln!(writer, "}};")?;
+        writeln!(writer, "if {SHOULD_RETURN} {{ return NextFunc(None); }}")?;
+        writeln!(writer, "}}")?;
+        writeln!(writer, "}}")?;
+
+        Ok(())
+    }
+
+    /// Writes each group of blocks in `superblocks` as a superblock, e.g., one per function. A block may be in more
+    /// than one group, but each group must start with a different block. Only for `Runtime::Arviss`.
+    pub fn write_superblocks(
+        &mut self,
+        writer: &mut impl Write,
+        superblocks: &[Vec<Block>],
+    ) -> Result<(), BlockWriterError> {
+        self.begin(writer)?;
+        let blocks: BTreeMap<Address, Block> = superblocks
+            .iter()
+            .flatten()
+            .map(|block| (block.start, *block))
+            .collect();
+        self.write_block_switches(writer, &blocks)?;
+        for block in blocks.values() {
+            // Inline the blocks into the superblocks, so that they're optimized together.
+            writeln!(writer, "\n#[inline(always)]")?;
             self.write_block(writer, block)?;
         }
+        for superblock in superblocks {
+            self.write_superblock(writer, superblock)?;
+        }
 
         Ok(())
     }
@@ -115,7 +459,9 @@ impl HandleRv32i for BlockWriter<'_> {
         format!(
             r#"
             cpu.handle_trap(TrapCause::IllegalInstruction({ins}));
-        "#
+            if cpu.is_trapped() {{ {} }}
+        "#,
+            self.trap_exit()
         )
     }
 
@@ -134,8 +480,8 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(bimm),
-            self.pc.wrapping_add(4)
+            self.jump_target(self.pc.wrapping_add(bimm)),
+            self.jump_target(self.pc.wrapping_add(4))
         )
     }
 
@@ -154,8 +500,8 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(bimm),
-            self.pc.wrapping_add(4)
+            self.jump_target(self.pc.wrapping_add(bimm)),
+            self.jump_target(self.pc.wrapping_add(4))
         )
     }
 
@@ -174,8 +520,8 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(bimm),
-            self.pc.wrapping_add(4)
+            self.jump_target(self.pc.wrapping_add(bimm)),
+            self.jump_target(self.pc.wrapping_add(4))
         )
     }
 
@@ -194,8 +540,8 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(bimm),
-            self.pc.wrapping_add(4)
+            self.jump_target(self.pc.wrapping_add(bimm)),
+            self.jump_target(self.pc.wrapping_add(4))
         )
     }
 
@@ -214,8 +560,8 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(bimm),
-            self.pc.wrapping_add(4)
+            self.jump_target(self.pc.wrapping_add(bimm)),
+            self.jump_target(self.pc.wrapping_add(4))
         )
     }
 
@@ -234,8 +580,8 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(bimm),
-            self.pc.wrapping_add(4)
+            self.jump_target(self.pc.wrapping_add(bimm)),
+            self.jump_target(self.pc.wrapping_add(4))
         )
     }
 
@@ -253,9 +599,11 @@ impl HandleRv32i for BlockWriter<'_> {
                 }}
                 Err(address) => {{
                     cpu.handle_trap(TrapCause::LoadAccessFault(address));
+                    {}
                 }}
             }}
-        "#
+        "#,
+            self.trap_exit()
         )
     }
 
@@ -273,10 +621,12 @@ impl HandleRv32i for BlockWriter<'_> {
                 }}
                 Err(address) => {{
                     cpu.handle_trap(TrapCause::LoadAccessFault(address));
+                    {}
                 }}
             }}
     
-        "#
+        "#,
+            self.trap_exit()
         )
     }
 
@@ -294,9 +644,11 @@ impl HandleRv32i for BlockWriter<'_> {
                 }}
                 Err(address) => {{
                     cpu.handle_trap(TrapCause::LoadAccessFault(address));
+                    {}
                 }}
             }}
-        "#
+        "#,
+            self.trap_exit()
         )
     }
 
@@ -314,9 +666,11 @@ impl HandleRv32i for BlockWriter<'_> {
                 }}
                 Err(address) => {{
                     cpu.handle_trap(TrapCause::LoadAccessFault(address));
+                    {}
                 }}
             }}
-            "#
+            "#,
+            self.trap_exit()
         )
     }
 
@@ -332,9 +686,13 @@ impl HandleRv32i for BlockWriter<'_> {
                 Ok(half_word) => {{
                     cpu.wx({rd}, half_word as u32);
                 }}
-                Err(address) => cpu.handle_trap(TrapCause::LoadAccessFault(address)),
+                Err(address) => {{
+                    cpu.handle_trap(TrapCause::LoadAccessFault(address));
+                    {}
+                }}
             }}
-        "#
+        "#,
+            self.trap_exit()
         )
     }
 
@@ -441,14 +799,12 @@ impl HandleRv32i for BlockWriter<'_> {
         rs2: arviss::decoding::Reg,
         simm: u32,
     ) -> Self::Item {
-        format!(
-            r#"
-            if let Err(address) = 
-                cpu.write8(cpu.rx({rs1}).wrapping_add({simm}), (cpu.rx({rs2}) & 0xff) as u8)
-            {{
-                cpu.handle_trap(TrapCause::StoreAccessFault(address))
-            }}
-        "#
+        self.store(
+            "write8",
+            rs1,
+            &format!("(cpu.rx({rs2}) & 0xff) as u8"),
+            simm,
+            1,
         )
     }
 
@@ -458,15 +814,12 @@ impl HandleRv32i for BlockWriter<'_> {
         rs2: arviss::decoding::Reg,
         simm: u32,
     ) -> Self::Item {
-        format!(
-            r#"
-            if let Err(address) = cpu.write16(
-                cpu.rx({rs1}).wrapping_add({simm}),
-                (cpu.rx({rs2}) & 0xffff) as u16,
-            ) {{
-                cpu.handle_trap(TrapCause::StoreAccessFault(address))
-            }}
-        "#
+        self.store(
+            "write16",
+            rs1,
+            &format!("(cpu.rx({rs2}) & 0xffff) as u16"),
+            simm,
+            2,
         )
     }
 
@@ -476,13 +829,7 @@ impl HandleRv32i for BlockWriter<'_> {
         rs2: arviss::decoding::Reg,
         simm: u32,
     ) -> Self::Item {
-        format!(
-            r#"
-            if let Err(address) = cpu.write32(cpu.rx({rs1}).wrapping_add({simm}), cpu.rx({rs2})) {{
-                cpu.handle_trap(TrapCause::StoreAccessFault(address))
-            }}
-        "#
-        )
+        self.store("write32", rs1, &format!("cpu.rx({rs2})"), simm, 4)
     }
 
     fn auipc(&mut self, rd: arviss::decoding::Reg, uimm: u32) -> Self::Item {
@@ -510,7 +857,7 @@ impl HandleRv32i for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});   
         "#,
             self.pc.wrapping_add(4),
-            self.pc.wrapping_add(jimm)
+            self.jump_target(self.pc.wrapping_add(jimm))
         )
     }
 
@@ -697,21 +1044,35 @@ impl HandleRv32i for BlockWriter<'_> {
         _rd: arviss::decoding::Reg,
         _rs1: arviss::decoding::Reg,
     ) -> Self::Item {
-        "".to_string()
+        // The decoder doesn't distinguish `fence.i` from `fence`. Blocks end after a `fence.i`, so the host can act on
+        // it before the next instruction is fetched.
+        let is_fence_i = self
+            .instruction_at(self.pc)
+            .is_ok_and(|ins| (ins >> 12) & 7 == 1);
+        match is_fence_i {
+            true => "note_flush();".to_string(),
+            false => "".to_string(),
+        }
     }
 
     fn ecall(&mut self) -> Self::Item {
-        r#"
+        format!(
+            r#"
             cpu.handle_ecall();
-        "#
-        .to_string()
+            if cpu.is_trapped() {{ {} }}
+        "#,
+            self.trap_exit()
+        )
     }
 
     fn ebreak(&mut self) -> Self::Item {
-        r#"
+        format!(
+            r#"
             cpu.handle_ebreak();
-        "#
-        .to_string()
+            if cpu.is_trapped() {{ {} }}
+        "#,
+            self.trap_exit()
+        )
     }
 }
 
@@ -732,11 +1093,7 @@ impl HandleRv32c for BlockWriter<'_> {
         rs1p: arviss::decoding::Reg,
         imm: u32,
     ) -> Self::Item {
-        format!(
-            r#"
-            cpu.lw({rdp}, {rs1p}, {imm});
-        "#
-        )
+        self.lw(rdp, rs1p, imm)
     }
 
     fn c_sw(
@@ -745,11 +1102,7 @@ impl HandleRv32c for BlockWriter<'_> {
         rs2p: arviss::decoding::Reg,
         imm: u32,
     ) -> Self::Item {
-        format!(
-            r#"
-            cpu.sw({rs1p}, {rs2p}, {imm});            
-        "#
-        )
+        self.store("write32", rs1p, &format!("cpu.rx({rs2p})"), imm, 4)
     }
 
     fn c_sub(&mut self, rdrs1p: arviss::decoding::Reg, rs2p: arviss::decoding::Reg) -> Self::Item {
@@ -834,7 +1187,7 @@ impl HandleRv32c for BlockWriter<'_> {
             r#"
             cpu.set_next_pc(0x{:08x});
         "#,
-            self.pc.wrapping_add(imm)
+            self.jump_target(self.pc.wrapping_add(imm))
         )
     }
 
@@ -848,8 +1201,8 @@ impl HandleRv32c for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(imm),
-            self.pc.wrapping_add(2)
+            self.jump_target(self.pc.wrapping_add(imm)),
+            self.jump_target(self.pc.wrapping_add(2))
         )
     }
 
@@ -863,8 +1216,8 @@ impl HandleRv32c for BlockWriter<'_> {
             cpu.set_next_pc(0x{:08x});
         }}
         "#,
-            self.pc.wrapping_add(imm),
-            self.pc.wrapping_add(2)
+            self.jump_target(self.pc.wrapping_add(imm)),
+            self.jump_target(self.pc.wrapping_add(2))
         )
     }
 
@@ -881,18 +1234,16 @@ impl HandleRv32c for BlockWriter<'_> {
         self.is_jump = true;
         format!(
             r#"
+            let rs1_before = cpu.rx({rs1n0}); // Because rs1 might be ra.
             cpu.wx(Reg::RA, 0x{:08x});
-            cpu.set_next_pc(cpu.rx({rs1n0}) & !1);
+            cpu.set_next_pc(rs1_before & !1);
             "#,
             self.pc.wrapping_add(2)
         )
     }
 
     fn c_ebreak(&mut self) -> Self::Item {
-        r#"
-            cpu.ebreak();
-        "#
-        .to_string()
+        self.ebreak()
     }
 
     fn c_mv(&mut self, rd: arviss::decoding::Reg, rs2n0: arviss::decoding::Reg) -> Self::Item {
@@ -912,29 +1263,22 @@ impl HandleRv32c for BlockWriter<'_> {
     }
 
     fn c_lwsp(&mut self, rdn0: arviss::decoding::Reg, imm: u32) -> Self::Item {
-        format!(
-            r#"
-            cpu.lw({rdn0}, Reg::SP, {imm});
-        "#
-        )
+        self.lw(rdn0, Reg::SP, imm)
     }
 
     fn c_swsp(&mut self, rs2: arviss::decoding::Reg, imm: u32) -> Self::Item {
-        format!(
-            r#"
-            cpu.sw(Reg::SP, {rs2}, {imm});
-        "#
-        )
+        self.store("write32", Reg::SP, &format!("cpu.rx({rs2})"), imm, 4)
     }
 
     fn c_jal(&mut self, imm: u32) -> Self::Item {
         self.is_jump = true;
         format!(
             r#"
-            cpu.wx(Reg::RA, {0}.wrapping_add(2));
+            cpu.wx(Reg::RA, 0x{:08x});
             cpu.set_next_pc(0x{:08x});
         "#,
-            self.pc.wrapping_add(imm)
+            self.pc.wrapping_add(2),
+            self.jump_target(self.pc.wrapping_add(imm))
         )
     }
 
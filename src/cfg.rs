@@ -0,0 +1,164 @@
+use crate::block_finder::Block;
+use arviss::Address;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How control gets from one block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Fallthrough, // The block ends because the next one starts, or after a trap returns.
+    Taken,       // A conditional branch that is taken, or an unconditional jump.
+    NotTaken,    // A conditional branch that is not taken.
+    Call,        // A jump that links, e.g., `jal ra, target`.
+    Return,      // A return to the caller, e.g., `jalr zero, 0(ra)`.
+    Indirect,    // Any other jump through a register, e.g., via a jump table.
+    Trap,        // An `ecall` or `ebreak`.
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: Address,       // Start of the source block.
+    pub to: Option<Address>, // Start of the target block, or `None` if it isn't known statically.
+    pub kind: EdgeKind,
+}
+
+/// A function, i.e., the blocks reachable from a call target without following calls into other functions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub entry: Address,
+    pub blocks: BTreeSet<Address>, // Starts of the blocks in the function. Shared tails appear in every function.
+}
+
+/// The control-flow graph of a set of basic blocks.
+///
+/// Edge targets are addresses rather than blocks, because a direct jump may target an address outside of the code
+/// regions that were searched. Such targets have no block and no predecessors.
+#[derive(Clone, Debug, Default)]
+pub struct Cfg {
+    blocks: BTreeMap<Address, Block>,
+    successors: BTreeMap<Address, Vec<Edge>>,
+    predecessors: BTreeMap<Address, Vec<Edge>>,
+    function_entries: BTreeSet<Address>,
+}
+
+impl Cfg {
+    /// Builds a graph from blocks and the exits from their terminating instructions, which are keyed by the address of
+    /// the instruction rather than the block, because a block may have been split after its exits were recorded.
+    pub(crate) fn new(
+        blocks: Vec<Block>,
+        exits: &BTreeMap<Address, Vec<(Option<Address>, EdgeKind)>>,
+        function_entries: BTreeSet<Address>,
+    ) -> Self {
+        let blocks: BTreeMap<Address, Block> = blocks.into_iter().map(|b| (b.start, b)).collect();
+        let mut cfg = Self {
+            blocks,
+            function_entries,
+            ..Default::default()
+        };
+
+        for block in cfg.blocks.values() {
+            // A block that was never closed has a sentinel end before its start, and no exits of its own.
+            let end = block.end.max(block.start);
+            let mut edges: Vec<Edge> = exits
+                .range(block.start..end)
+                .flat_map(|(_, exits)| exits)
+                .map(|&(to, kind)| Edge {
+                    from: block.start,
+                    to,
+                    kind,
+                })
+                .collect();
+
+            // A block with no exits of its own ends because another block starts immediately after it.
+            if edges.is_empty() && cfg.blocks.contains_key(&block.end) {
+                edges.push(Edge {
+                    from: block.start,
+                    to: Some(block.end),
+                    kind: EdgeKind::Fallthrough,
+                });
+            }
+
+            for edge in &edges {
+                if let Some(to) = edge.to {
+                    if cfg.blocks.contains_key(&to) {
+                        cfg.predecessors.entry(to).or_default().push(*edge);
+                    }
+                }
+            }
+            cfg.successors.insert(block.start, edges);
+        }
+
+        cfg
+    }
+
+    /// The blocks, in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// The block starting at `start`.
+    pub fn block(&self, start: Address) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    /// The edges out of the block starting at `start`.
+    pub fn successors(&self, start: Address) -> &[Edge] {
+        self.successors.get(&start).map_or(&[], Vec::as_slice)
+    }
+
+    /// The edges into the block starting at `start`.
+    pub fn predecessors(&self, start: Address) -> &[Edge] {
+        self.predecessors.get(&start).map_or(&[], Vec::as_slice)
+    }
+
+    /// All of the edges, grouped by source block.
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.successors.values().flatten()
+    }
+
+    /// The entry points of functions, i.e., the root that discovery started from, and the targets of direct calls.
+    pub fn function_entries(&self) -> impl Iterator<Item = &Address> {
+        self.function_entries.iter()
+    }
+
+    /// Partitions the blocks into functions by following intra-procedural edges from each function entry. Calls are
+    /// not followed, and neither are jumps to other function entries, which are treated as tail calls.
+    pub fn functions(&self) -> Vec<Function> {
+        self.function_entries
+            .iter()
+            .filter(|entry| self.blocks.contains_key(entry))
+            .map(|&entry| {
+                let mut blocks = BTreeSet::new();
+                let mut pending = vec![entry];
+                while let Some(start) = pending.pop() {
+                    if !blocks.insert(start) {
+                        continue;
+                    }
+                    for edge in self.successors(start) {
+                        let intra_procedural = matches!(
+                            edge.kind,
+                            EdgeKind::Fallthrough
+                                | EdgeKind::Taken
+                                | EdgeKind::NotTaken
+                                | EdgeKind::Indirect
+                        );
+                        if let (true, Some(to)) = (intra_procedural, edge.to) {
+                            if self.blocks.contains_key(&to) && !self.function_entries.contains(&to)
+                            {
+                                pending.push(to);
+                            }
+                        }
+                    }
+                }
+                Function { entry, blocks }
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
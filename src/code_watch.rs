@@ -0,0 +1,170 @@
+use crate::block_finder::*;
+use crate::jit::Cpu;
+use arviss::decoding::Reg;
+use arviss::Address;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+/// The granularity at which writes to code are tracked.
+pub const PAGE_SIZE: u32 = 4096;
+
+// How generated code encodes a change for `take_code_change`. Anything else is a write, with the address in the low
+// 32 bits and the length in the high 32 bits.
+pub(crate) const NO_CODE_CHANGE: u64 = 0;
+pub(crate) const CODE_FLUSH: u64 = u64::MAX;
+
+/// Something that may have made compiled code stale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeChange {
+    // A store of `len` bytes to a code region.
+    Write { addr: Address, len: u32 },
+    // A `fence.i`, after which instruction fetches must see all earlier stores.
+    Flush,
+}
+
+impl CodeChange {
+    /// Decodes the value returned by a library's `take_code_change`.
+    pub(crate) fn from_raw(raw: u64) -> Option<Self> {
+        match raw {
+            NO_CODE_CHANGE => None,
+            CODE_FLUSH => Some(CodeChange::Flush),
+            _ => Some(CodeChange::Write {
+                addr: raw as u32,
+                len: (raw >> 32) as u32,
+            }),
+        }
+    }
+
+    /// The change that the interpreter would make by running `ins`, judging by `cpu`'s registers before it runs. Only
+    /// stores and `fence.i` can change code.
+    pub fn before_interpreting(cpu: &Cpu, ins: u32) -> Option<Self> {
+        let write = |rs1: u32, offset: u32, len: u32| {
+            let addr = cpu.rx(Reg::from(rs1)).wrapping_add(offset);
+            Some(CodeChange::Write { addr, len })
+        };
+        let funct3 = (ins >> 12) & 7;
+        match ins & 0x7f {
+            // sb, sh, sw.
+            0x23 if funct3 <= 2 => {
+                let offset = (((ins as i32) >> 25) << 5) as u32 | ((ins >> 7) & 0x1f);
+                write((ins >> 15) & 0x1f, offset, 1 << funct3)
+            }
+            // fence.i.
+            0x0f if funct3 == 1 => Some(CodeChange::Flush),
+            _ => match (ins & 3, (ins >> 13) & 7) {
+                // c.sw.
+                (0, 6) => {
+                    let offset = ((ins >> 7) & 0x38) | ((ins >> 4) & 0x4) | ((ins << 1) & 0x40);
+                    write(8 + ((ins >> 7) & 7), offset, 4)
+                }
+                // c.swsp.
+                (2, 6) => {
+                    let offset = ((ins >> 7) & 0x3c) | ((ins >> 1) & 0xc0);
+                    write(u32::from(Reg::SP), offset, 4)
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Whether `ins` is one of the stores that `CodeChange::before_interpreting` looks for, i.e., one that can write to
+/// code.
+pub fn is_store(ins: u32) -> bool {
+    match ins & 3 {
+        3 => (ins & 0x7f) == 0x23 && (ins >> 12) & 7 <= 2,
+        quadrant => quadrant != 1 && (ins >> 13) & 7 == 6,
+    }
+}
+
+/// Tracks which pages hold compiled blocks, and which pages of code have been written to, so that stale blocks can be
+/// found and dropped. Blocks in pages that have been written to are never compiled again, as they would be compiled
+/// from the original code rather than what's in memory, so they're left to the interpreter.
+#[derive(Clone, Debug, Default)]
+pub struct CodeWatch {
+    blocks: BTreeMap<Address, Block>,        // The compiled blocks.
+    pages: BTreeMap<u32, BTreeSet<Address>>, // Starts of the compiled blocks in each page.
+    written: BTreeSet<u32>,                  // Pages of code that have been written to.
+}
+
+impl CodeWatch {
+    /// Starts watching a block that has been compiled.
+    pub fn watch(&mut self, block: &Block) {
+        self.blocks.insert(block.start, *block);
+        for page in pages(block.start, block.end) {
+            self.pages.entry(page).or_default().insert(block.start);
+        }
+    }
+
+    pub fn is_watched(&self, start: Address) -> bool {
+        self.blocks.contains_key(&start)
+    }
+
+    /// Whether `block` is in a page that has been written to, meaning that it shouldn't be compiled.
+    pub fn is_stale(&self, block: &Block) -> bool {
+        self.written
+            .range(pages(block.start, block.end))
+            .next()
+            .is_some()
+    }
+
+    /// Records `change`, and stops watching the blocks that it made stale. Returns their starts, so that the caller
+    /// can stop running them. A flush compares every compiled block against `cpu`'s memory, which catches writes that
+    /// weren't seen, e.g., by the host.
+    pub fn apply(&mut self, change: CodeChange, cpu: &Cpu, regions: &[CodeRegion]) -> Vec<Address> {
+        let written: Vec<u32> = match change {
+            CodeChange::Write { addr, len } => {
+                let end = addr.saturating_add(len.max(1));
+                let touches_code = regions
+                    .iter()
+                    .any(|region| region.contains(addr) || region.contains(end - 1));
+                if !touches_code {
+                    return Vec::new();
+                }
+                pages(addr, end).collect()
+            }
+            CodeChange::Flush => self
+                .blocks
+                .values()
+                .filter(|block| !matches_memory(block, cpu, regions))
+                .flat_map(|block| pages(block.start, block.end))
+                .collect(),
+        };
+
+        let mut stale = BTreeSet::new();
+        for page in written {
+            self.written.insert(page);
+            stale.extend(self.pages.remove(&page).unwrap_or_default());
+        }
+        for start in &stale {
+            if let Some(block) = self.blocks.remove(start) {
+                for page in pages(block.start, block.end) {
+                    if let Some(starts) = self.pages.get_mut(&page) {
+                        starts.remove(start);
+                    }
+                }
+            }
+        }
+        stale.into_iter().collect()
+    }
+}
+
+// The pages that [start, end) touches.
+fn pages(start: Address, end: Address) -> RangeInclusive<u32> {
+    start / PAGE_SIZE..=end.saturating_sub(1).max(start) / PAGE_SIZE
+}
+
+// Whether the code that `block` was compiled from is still in memory.
+fn matches_memory(block: &Block, cpu: &Cpu, regions: &[CodeRegion]) -> bool {
+    (block.start..block.end).step_by(2).all(|addr| {
+        let original = regions
+            .iter()
+            .find(|region| region.contains(addr))
+            .and_then(|region| {
+                let offset = (addr - region.base) as usize;
+                region.mem.get(offset..offset + 2)
+            })
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+        original.is_some() && cpu.read16(addr).ok() == original
+    })
+}
@@ -0,0 +1,439 @@
+use crate::arviss_rlib::*;
+use crate::block_finder::*;
+use crate::code_watch::*;
+use crate::jit::*;
+use crate::library_set::*;
+use arviss::Address;
+use libloading::Library;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, Weak};
+use std::thread::JoinHandle;
+use tempdir::TempDir;
+
+type BlockMap = HashMap<Address, CompiledBlock>;
+
+/// Compiled blocks, shared between the worker that publishes them and the dispatchers that run them.
+///
+/// A published map is never modified. Publishing a batch swaps in a new map that includes it, so a reader sees either
+/// all of a batch or none of it, and never waits for rustc. The libraries live here too. Each map that is swapped out
+/// may still be in use by a dispatcher, so a library whose blocks have all been removed stays loaded until every map
+/// that had them has been dropped.
+///
+/// Only one dispatcher may run native code from the map at a time. Each library reports writes to code through a
+/// single slot, so a dispatcher could take another's report, and the blocks that the other made stale would keep
+/// running. Other threads may hold snapshots, e.g., to look blocks up, while one of them runs.
+#[derive(Default)]
+pub struct SharedBlockMap {
+    blocks: RwLock<Arc<BlockMap>>,
+    libs: Mutex<SharedLibraries>,
+}
+
+impl SharedBlockMap {
+    /// The blocks that have been published so far.
+    pub fn snapshot(&self) -> Arc<BlockMap> {
+        self.blocks
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Unloads the libraries that have no blocks in any map that is still in use. Returns the number unloaded. This
+    /// happens anyway whenever blocks are published or removed.
+    pub fn unload_unused(&self) -> usize {
+        self.lock_libs().unload_unused()
+    }
+
+    /// Where each loaded library was loaded from, oldest first.
+    pub fn library_paths(&self) -> Vec<PathBuf> {
+        self.lock_libs()
+            .set
+            .paths()
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    // Loads the library at `path`, then publishes the blocks that `load_blocks` loads from it.
+    fn publish(
+        &self,
+        path: PathBuf,
+        load_blocks: impl FnOnce(&Library, Generation) -> Result<Vec<CompiledBlock>, JitError>,
+    ) -> Result<(), JitError> {
+        let mut libs = self.lock_libs();
+        let (generation, lib) = libs.set.load(path)?;
+        let compiled = match load_blocks(lib, generation) {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                libs.set.unload(generation);
+                return Err(err);
+            }
+        };
+        libs.set.retain(generation, compiled.len());
+
+        let mut blocks = self.blocks.write().unwrap_or_else(PoisonError::into_inner);
+        let mut next = BlockMap::clone(&blocks);
+        next.extend(compiled.into_iter().map(|c| (c.block.start, c)));
+        libs.replace(&mut blocks, next, Vec::new());
+        Ok(())
+    }
+
+    // Drops blocks whose code has changed, and stops other blocks from chaining to them. Their libraries stay loaded
+    // while other snapshots still have them.
+    fn remove(&self, starts: &[Address]) {
+        let mut libs = self.lock_libs();
+        let mut blocks = self.blocks.write().unwrap_or_else(PoisonError::into_inner);
+        let mut next = BlockMap::clone(&blocks);
+        let mut retired = Vec::new();
+        for start in starts {
+            if let Some(compiled) = next.remove(start) {
+                compiled.unlink();
+                if libs.set.release(compiled.generation) {
+                    retired.push(compiled.generation);
+                }
+            }
+        }
+        libs.replace(&mut blocks, next, retired);
+    }
+
+    fn lock_libs(&self) -> MutexGuard<'_, SharedLibraries> {
+        self.libs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// The libraries behind a `SharedBlockMap`, and the maps that might still be running their code. Maps are numbered in
+// the order that they were published, so a library that was retired when map `n` was published can be unloaded once
+// all of the maps before `n` have gone.
+#[derive(Default)]
+struct SharedLibraries {
+    set: LibrarySet,
+    version: u64,                     // The number of the published map.
+    maps: Vec<(u64, Weak<BlockMap>)>, // Maps that were swapped out, oldest first.
+    retired: Vec<(Generation, u64)>,  // Libraries with no blocks, and the first map without them.
+}
+
+impl SharedLibraries {
+    // Publishes `next`, which no longer has any blocks from the `retired` libraries.
+    fn replace(&mut self, published: &mut Arc<BlockMap>, next: BlockMap, retired: Vec<Generation>) {
+        let previous = std::mem::replace(published, Arc::new(next));
+        self.maps.push((self.version, Arc::downgrade(&previous)));
+        self.version += 1;
+        let version = self.version;
+        self.retired
+            .extend(retired.into_iter().map(|generation| (generation, version)));
+        drop(previous);
+        self.unload_unused();
+    }
+
+    fn unload_unused(&mut self) -> usize {
+        self.maps.retain(|(_, map)| map.strong_count() > 0);
+        let oldest = self
+            .maps
+            .first()
+            .map_or(self.version, |(version, _)| *version);
+        let before = self.retired.len();
+        let set = &mut self.set;
+        self.retired.retain(|&(generation, version)| {
+            let unused = version <= oldest;
+            if unused {
+                set.unload(generation);
+            }
+            !unused
+        });
+        before - self.retired.len()
+    }
+}
+
+// What the worker sends back when it has finished with a batch.
+struct BatchDone {
+    starts: Vec<Address>,
+    result: Result<(), JitError>,
+}
+
+/// Compiles basic blocks on a worker thread, so that the caller can carry on interpreting while rustc runs.
+///
+/// Blocks are queued in batches. Each batch becomes one library, and its blocks appear together when the caller next
+/// calls `poll`.
+pub struct AsyncCompiler {
+    code: Arc<Vec<(Address, Vec<u8>)>>, // A copy of the code, as the worker can't borrow it.
+    shared: Arc<SharedBlockMap>,
+    snapshot: Arc<BlockMap>, // What the dispatcher looks blocks up in. Refreshed by `poll`.
+    batches: Option<Sender<Vec<Block>>>,
+    done: Receiver<BatchDone>,
+    worker: Option<JoinHandle<()>>,
+    roots: BTreeSet<Address>, // Every address that blocks have been found from, including missed ones.
+    missed: BTreeSet<Address>, // Addresses that weren't in the block map, and haven't been queued yet.
+    queued: BTreeSet<Address>, // Starts of blocks that have been queued but not published.
+    failed: BTreeSet<Address>, // Starts of blocks in batches that failed to compile.
+    in_flight: usize,          // Batches that the worker hasn't finished with.
+    code_watch: CodeWatch,
+}
+
+impl AsyncCompiler {
+    /// Starts a worker that compiles blocks from the code in `regions`, writing its files into `dir`.
+    pub fn new(dir: TempDir, regions: &[CodeRegion]) -> Result<Self, JitError> {
+        let code: Arc<Vec<(Address, Vec<u8>)>> = Arc::new(
+            regions
+                .iter()
+                .map(|region| (region.base, region.mem.to_vec()))
+                .collect(),
+        );
+        let shared = Arc::new(SharedBlockMap::default());
+        let (batches, batch_receiver) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel();
+        let worker = {
+            let code = code.clone();
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("compile-worker".to_string())
+                .spawn(move || run_worker(dir, &code, &shared, batch_receiver, done_sender))?
+        };
+        Ok(Self {
+            code,
+            shared,
+            snapshot: Arc::default(),
+            batches: Some(batches),
+            done,
+            worker: Some(worker),
+            roots: BTreeSet::new(),
+            missed: BTreeSet::new(),
+            queued: BTreeSet::new(),
+            failed: BTreeSet::new(),
+            in_flight: 0,
+            code_watch: CodeWatch::default(),
+        })
+    }
+
+    /// The map that the worker publishes into, for sharing with other threads. Only one of them may run native code
+    /// from it at a time. See `SharedBlockMap`.
+    pub fn shared_block_map(&self) -> &Arc<SharedBlockMap> {
+        &self.shared
+    }
+
+    pub fn get(&self, addr: Address) -> Option<&ArvissFunc> {
+        self.snapshot.get(&addr).map(|compiled| &compiled.func)
+    }
+
+    pub fn compiled_block(&self, addr: Address) -> Option<&CompiledBlock> {
+        self.snapshot.get(&addr)
+    }
+
+    /// Records that there was no compiled block for `addr`, so that `queue_missed` can queue it later.
+    pub fn record_miss(&mut self, addr: Address) {
+        if !self.roots.contains(&addr) && !self.snapshot.contains_key(&addr) {
+            self.missed.insert(addr);
+        }
+    }
+
+    /// The number of missed addresses that are waiting to be queued.
+    pub fn missed_count(&self) -> usize {
+        self.missed.len()
+    }
+
+    /// The number of blocks that have been queued but not published yet.
+    pub fn pending_count(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Queues the code that is reachable from `entry`. Returns the number of blocks queued.
+    pub fn queue(&mut self, entry: Address) -> Result<usize, JitError> {
+        self.roots.insert(entry);
+        self.queue_new_blocks()
+    }
+
+    /// Queues blocks found from the addresses passed to `record_miss`. Returns the number of blocks queued.
+    pub fn queue_missed(&mut self) -> Result<usize, JitError> {
+        let missed = std::mem::take(&mut self.missed);
+        self.roots.extend(missed);
+        self.queue_new_blocks()
+    }
+
+    /// Picks up any batches that the worker has published since the last call, without waiting. Returns the number of
+    /// blocks that became available, or the first error that the worker reported.
+    pub fn poll(&mut self) -> Result<usize, JitError> {
+        let mut finished = Vec::new();
+        while let Ok(batch) = self.done.try_recv() {
+            finished.push(batch);
+        }
+        self.finish(finished)
+    }
+
+    /// Waits for the worker to finish everything that has been queued. Returns the number of blocks that became
+    /// available, or the first error that the worker reported.
+    pub fn wait(&mut self) -> Result<usize, JitError> {
+        let mut finished = Vec::new();
+        while finished.len() < self.in_flight {
+            let Ok(batch) = self.done.recv() else {
+                return Err(JitError::WorkerStopped);
+            };
+            finished.push(batch);
+        }
+        self.finish(finished)
+    }
+
+    /// Drops any compiled blocks that `change` made stale, so that they're interpreted from now on. Returns the number
+    /// of blocks dropped. Their libraries are unloaded once no snapshot has their blocks.
+    pub fn code_changed(&mut self, change: CodeChange, cpu: &Cpu) -> usize {
+        let regions = code_regions(&self.code);
+        let stale = self.code_watch.apply(change, cpu, &regions);
+        if !stale.is_empty() {
+            self.shared.remove(&stale);
+            self.refresh();
+        }
+        stale.len()
+    }
+
+    // Accounts for finished batches and refreshes the snapshot if any of them published anything.
+    fn finish(&mut self, finished: Vec<BatchDone>) -> Result<usize, JitError> {
+        if finished.is_empty() {
+            return Ok(0);
+        }
+        self.refresh();
+        self.in_flight -= finished.len();
+
+        // Blocks from batches that failed aren't queued again, as they would only fail again.
+        let mut published = 0;
+        let mut first_error = None;
+        for batch in finished {
+            for start in &batch.starts {
+                self.queued.remove(start);
+                published += usize::from(self.snapshot.contains_key(start));
+            }
+            if let Err(err) = batch.result {
+                self.failed.extend(batch.starts);
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(published),
+        }
+    }
+
+    // Takes the latest published blocks, dropping any whose code was written to while the worker was compiling them
+    // from the original image, and watching the rest.
+    fn refresh(&mut self) {
+        loop {
+            self.snapshot = self.shared.snapshot();
+            let mut stale = Vec::new();
+            for compiled in self.snapshot.values() {
+                if self.code_watch.is_stale(&compiled.block) {
+                    stale.push(compiled.block.start);
+                } else if !self.code_watch.is_watched(compiled.block.start) {
+                    self.code_watch.watch(&compiled.block);
+                }
+            }
+            if stale.is_empty() {
+                break;
+            }
+            self.shared.remove(&stale);
+        }
+        // The snapshot that was just replaced may have been the last one with some retired library's blocks.
+        self.shared.unload_unused();
+    }
+
+    // Finds the blocks reachable from all of the roots, as `BlockCompiler` does, then queues the ones that aren't
+    // already compiled or queued as a single batch.
+    fn queue_new_blocks(&mut self) -> Result<usize, JitError> {
+        let regions = code_regions(&self.code);
+        let roots: Vec<Address> = self
+            .roots
+            .iter()
+            .copied()
+            .filter(|&addr| regions.iter().any(|region| region.contains(addr)))
+            .collect();
+        let mut block_finder = BlockFinder::with_regions(regions);
+        let blocks: Vec<Block> = block_finder
+            .add_entry_points(&roots)?
+            .into_iter()
+            .filter(|block| {
+                !self.snapshot.contains_key(&block.start)
+                    && !self.queued.contains(&block.start)
+                    && !self.failed.contains(&block.start)
+                    && !self.code_watch.is_stale(block)
+            })
+            .collect();
+        if blocks.is_empty() {
+            return Ok(0);
+        }
+
+        let queued = blocks.len();
+        let starts: Vec<Address> = blocks.iter().map(|block| block.start).collect();
+        let sent = self
+            .batches
+            .as_ref()
+            .is_some_and(|batches| batches.send(blocks).is_ok());
+        if !sent {
+            return Err(JitError::WorkerStopped);
+        }
+        self.queued.extend(starts);
+        self.in_flight += 1;
+        Ok(queued)
+    }
+}
+
+impl Drop for AsyncCompiler {
+    // Closes the queue and waits for the worker to finish the batch that it's on.
+    fn drop(&mut self) {
+        self.batches = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn code_regions(code: &[(Address, Vec<u8>)]) -> Vec<CodeRegion<'_>> {
+    code.iter()
+        .map(|(base, mem)| CodeRegion::new(*base, mem))
+        .collect()
+}
+
+// Compiles batches until the queue is closed, publishing each one as it is loaded.
+fn run_worker(
+    dir: TempDir,
+    code: &[(Address, Vec<u8>)],
+    shared: &SharedBlockMap,
+    batches: Receiver<Vec<Block>>,
+    done: Sender<BatchDone>,
+) {
+    let regions = code_regions(code);
+    let mut arviss = None;
+    for (index, blocks) in batches.into_iter().enumerate() {
+        let name = format!("batch_{index}");
+        let result = compile_batch(&dir, &name, &regions, &blocks, &mut arviss, shared);
+        let starts = blocks.iter().map(|block| block.start).collect();
+        if done.send(BatchDone { starts, result }).is_err() {
+            break;
+        }
+    }
+}
+
+fn compile_batch(
+    dir: &TempDir,
+    name: &str,
+    regions: &[CodeRegion],
+    blocks: &[Block],
+    arviss: &mut Option<ArvissRlib>,
+    shared: &SharedBlockMap,
+) -> Result<(), JitError> {
+    let arviss = match arviss {
+        Some(arviss) => arviss,
+        None => arviss.insert(ArvissRlib::locate()?),
+    };
+    let library_path = compile_library(dir.path(), name, regions, blocks, None, arviss)?;
+    let chain_symbols = chain_symbols(blocks, None);
+    shared.publish(library_path, |lib, generation| {
+        blocks
+            .iter()
+            .map(|block| {
+                load_block(
+                    lib,
+                    generation,
+                    regions,
+                    block,
+                    &chain_symbols[&block.start],
+                )
+            })
+            .collect()
+    })
+}
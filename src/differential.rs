@@ -0,0 +1,306 @@
+use crate::arviss_rlib::*;
+use crate::block_finder::*;
+use crate::code_watch::*;
+use crate::jit::*;
+use crate::library_set::*;
+use arviss::decoding::Reg;
+use arviss::{disassembler::Disassembler, Address, DispatchRv32ic};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use tempdir::TempDir;
+
+/// Runs compiled blocks and the interpreter from the same state, and reports where they disagree.
+///
+/// Each block is run natively on one CPU and interpreted on another, then the two are compared: all 32 registers, the
+/// next pc, the trap cause, and the memory that the interpreter's stores touched. When a block diverges, each of its
+/// instructions is compiled as a block of its own and checked in the same way, after interpreting the instructions
+/// before it, to find the first one that diverges.
+pub struct DifferentialTester<'a> {
+    temp_dir: TempDir,
+    regions: Vec<CodeRegion<'a>>,
+    libs: LibrarySet, // Keeps the compiled blocks loaded for as long as the tester is around.
+    arviss: Option<ArvissRlib>,
+}
+
+/// Where a compiled block first disagreed with the interpreter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub block: Block,
+
+    /// The first instruction that diverges on its own. None if they all agree on their own, in which case it's the
+    /// block as a whole that's wrong.
+    pub instruction: Option<DivergentInstruction>,
+
+    /// How the native run differs from the interpreted one, for the instruction if there is one, otherwise for the
+    /// block.
+    pub differences: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DivergentInstruction {
+    pub addr: Address,
+    pub ins: u32,
+    pub disassembly: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block 0x{:08x}..0x{:08x} diverges",
+            self.block.start, self.block.end
+        )?;
+        match &self.instruction {
+            Some(ins) => write!(
+                f,
+                " at 0x{:08x}: {:08x} {}",
+                ins.addr, ins.ins, ins.disassembly
+            )?,
+            None => write!(f, " as a whole")?,
+        }
+        for difference in &self.differences {
+            write!(f, "\n    {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> DifferentialTester<'a> {
+    pub fn new(dir: TempDir, regions: Vec<CodeRegion<'a>>) -> Self {
+        Self {
+            temp_dir: dir,
+            regions,
+            libs: LibrarySet::default(),
+            arviss: None,
+        }
+    }
+
+    /// Checks each of `blocks`, starting from a CPU made by `setup` with its pc moved to the start of the block.
+    /// `setup` must make the same CPU every time. Returns a divergence for each block that disagrees with the
+    /// interpreter.
+    pub fn check(
+        &mut self,
+        blocks: &[Block],
+        setup: impl Fn() -> Cpu,
+    ) -> Result<Vec<Divergence>, JitError> {
+        let compiled = self.compile(blocks)?;
+        let divergent: Vec<(Block, Vec<String>)> = blocks
+            .iter()
+            .filter_map(|block| {
+                let differences = compare(block, 0, &compiled[&block.start], &setup);
+                (!differences.is_empty()).then_some((*block, differences))
+            })
+            .collect();
+        if divergent.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Compile each instruction of the divergent blocks on its own, all in one library.
+        let instructions: BTreeSet<Block> = divergent
+            .iter()
+            .flat_map(|(block, _)| self.instructions(block))
+            .collect();
+        let instructions: Vec<Block> = instructions.into_iter().collect();
+        let singles = self.compile(&instructions)?;
+
+        Ok(divergent
+            .into_iter()
+            .map(|(block, differences)| {
+                self.first_divergent_instruction(&block, &singles, &setup)
+                    .unwrap_or(Divergence {
+                        block,
+                        instruction: None,
+                        differences,
+                    })
+            })
+            .collect())
+    }
+
+    // Splits `block` into a block per instruction.
+    fn instructions(&self, block: &Block) -> Vec<Block> {
+        let mut instructions = Vec::new();
+        let mut addr = block.start;
+        while addr < block.end {
+            let Ok(ins) = read_instruction_from(&self.regions, addr) else {
+                break;
+            };
+            let end = addr + if (ins & 3) == 3 { 4 } else { 2 };
+            instructions.push(Block {
+                start: addr,
+                end,
+                ..*block
+            });
+            addr = end;
+        }
+        instructions
+    }
+
+    fn first_divergent_instruction(
+        &self,
+        block: &Block,
+        singles: &BTreeMap<Address, CompiledBlock>,
+        setup: &impl Fn() -> Cpu,
+    ) -> Option<Divergence> {
+        self.instructions(block)
+            .iter()
+            .enumerate()
+            .find_map(|(before, single)| {
+                let differences = compare(block, before as u32, &singles[&single.start], setup);
+                (!differences.is_empty()).then(|| {
+                    let ins =
+                        read_instruction_from(&self.regions, single.start).unwrap_or_default();
+                    Divergence {
+                        block: *block,
+                        instruction: Some(DivergentInstruction {
+                            addr: single.start,
+                            ins,
+                            disassembly: Disassembler.dispatch(ins),
+                        }),
+                        differences,
+                    }
+                })
+            })
+    }
+
+    // Compiles `blocks` into a library of their own and loads them.
+    fn compile(&mut self, blocks: &[Block]) -> Result<BTreeMap<Address, CompiledBlock>, JitError> {
+        let name = format!("differential_{}", self.libs.next_generation());
+        let arviss = match &self.arviss {
+            Some(arviss) => arviss,
+            None => self.arviss.insert(ArvissRlib::locate()?),
+        };
+        let path = compile_library(
+            self.temp_dir.path(),
+            &name,
+            &self.regions,
+            blocks,
+            None,
+            arviss,
+        )?;
+        let (generation, lib) = self.libs.load(path)?;
+        let chain_symbols = chain_symbols(blocks, None);
+        blocks
+            .iter()
+            .map(|block| {
+                let chain_symbol = &chain_symbols[&block.start];
+                let compiled = load_block(lib, generation, &self.regions, block, chain_symbol)?;
+                Ok((block.start, compiled))
+            })
+            .collect()
+    }
+}
+
+// Everything that running a block can change, as far as the comparison goes.
+#[derive(Debug, PartialEq, Eq)]
+struct State {
+    registers: Vec<u32>,
+    pc: Address,
+    trap: String,
+    memory: BTreeMap<Address, Option<u8>>, // The bytes that the interpreter's stores touched, if they can be read.
+}
+
+impl State {
+    fn of(cpu: &Cpu, touched: &BTreeSet<Address>) -> Self {
+        Self {
+            registers: (0..32).map(|r| cpu.rx(Reg::from(r))).collect(),
+            pc: cpu.pc(),
+            trap: format!("{:?}", cpu.trap_cause()),
+            memory: touched
+                .iter()
+                .map(|&addr| (addr, cpu.read8(addr).ok()))
+                .collect(),
+        }
+    }
+
+    // Describes how `self`, from the native run, differs from `interpreted`.
+    fn differences(&self, interpreted: &State) -> Vec<String> {
+        let mut differences = Vec::new();
+        for (r, (native, interpreted)) in self
+            .registers
+            .iter()
+            .zip(&interpreted.registers)
+            .enumerate()
+        {
+            if native != interpreted {
+                differences.push(format!(
+                    "x{r}: native 0x{native:08x}, interpreted 0x{interpreted:08x}"
+                ));
+            }
+        }
+        if self.pc != interpreted.pc {
+            differences.push(format!(
+                "pc: native 0x{:08x}, interpreted 0x{:08x}",
+                self.pc, interpreted.pc
+            ));
+        }
+        if self.trap != interpreted.trap {
+            differences.push(format!(
+                "trap: native {}, interpreted {}",
+                self.trap, interpreted.trap
+            ));
+        }
+        for (addr, native) in &self.memory {
+            let interpreted = interpreted.memory.get(addr).copied().flatten();
+            if *native != interpreted {
+                differences.push(format!(
+                    "memory at 0x{addr:08x}: native {native:?}, interpreted {interpreted:?}"
+                ));
+            }
+        }
+        differences
+    }
+}
+
+// Runs `compiled` natively on one CPU from `setup`, and its instructions in the interpreter on another, after both
+// have interpreted the first `before` instructions of `block`, then describes the differences. There's nothing to
+// compare if those instructions trap.
+fn compare(
+    block: &Block,
+    before: u32,
+    compiled: &CompiledBlock,
+    setup: &impl Fn() -> Cpu,
+) -> Vec<String> {
+    let mut touched = BTreeSet::new();
+    let mut native = start_at(setup, block.start);
+    let mut interpreted = start_at(setup, block.start);
+    interpret(&mut native, before, &mut touched);
+    interpret(&mut interpreted, before, &mut touched);
+    if interpreted.is_trapped() {
+        return Vec::new();
+    }
+
+    (compiled.func)(&mut native);
+    compiled.take_code_change(); // So that it isn't reported after the next run.
+    native.transfer();
+
+    interpret(&mut interpreted, compiled.instructions, &mut touched);
+    if !interpreted.is_trapped() {
+        interpreted.transfer();
+    }
+
+    State::of(&native, &touched).differences(&State::of(&interpreted, &touched))
+}
+
+fn start_at(setup: &impl Fn() -> Cpu, addr: Address) -> Cpu {
+    let mut cpu = setup();
+    cpu.set_next_pc(addr);
+    cpu.transfer();
+    cpu
+}
+
+// Interprets up to `count` instructions, stopping at the first that traps, and adds the bytes that they store to
+// `touched`. Like a block that the host hasn't transferred from yet, it leaves the pc on the last instruction.
+fn interpret(cpu: &mut Cpu, count: u32, touched: &mut BTreeSet<Address>) {
+    for _ in 0..count {
+        let Ok(ins) = cpu.fetch() else {
+            break;
+        };
+        if let Some(CodeChange::Write { addr, len }) = CodeChange::before_interpreting(cpu, ins) {
+            touched.extend((0..len).map(|i| addr.wrapping_add(i)));
+        }
+        cpu.dispatch(ins);
+        if cpu.is_trapped() {
+            break;
+        }
+    }
+}
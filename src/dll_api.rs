@@ -1,3 +1,8 @@
+use crate::jit::Cpu;
+use arviss::decoding::Reg;
+use arviss::platforms::basic::*;
+use core::ffi::c_void;
+
 pub type BigComplicatedCalculationFunc = extern "C" fn(i64, i64) -> i64;
 
 #[no_mangle]
@@ -9,3 +14,102 @@ pub extern "C" fn big_complicated_calculation(a: i64, b: i64) -> i64 {
 pub extern "C" fn return_another_function() -> BigComplicatedCalculationFunc {
     big_complicated_calculation
 }
+
+// The ABI for self-contained blocks. See `native_abi.rs`.
+include!("native_abi.rs");
+
+// Host-side glue that lets self-contained blocks run against an arviss CPU.
+
+impl RegisterFile {
+    /// Copies the registers out of `cpu`.
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        let mut regs = Self {
+            next_pc: cpu.pc(),
+            ..Default::default()
+        };
+        for (i, x) in regs.x.iter_mut().enumerate() {
+            *x = cpu.rx(Reg::from(i as u32));
+        }
+        regs
+    }
+
+    /// Copies the registers back into `cpu`, ready for it to transfer control to `next_pc`.
+    pub fn store(&self, cpu: &mut Cpu) {
+        for (i, x) in self.x.iter().enumerate().skip(1) {
+            cpu.wx(Reg::from(i as u32), *x);
+        }
+        cpu.set_next_pc(self.next_pc);
+    }
+}
+
+impl HostApi {
+    /// Creates callbacks that forward to `cpu`.
+    ///
+    /// # Safety
+    ///
+    /// `cpu` must outlive the returned `HostApi` and must not be accessed by anything else while a block is using it.
+    pub unsafe fn for_cpu(cpu: *mut Cpu) -> Self {
+        Self {
+            ctx: cpu as *mut c_void,
+            read8: host_read8,
+            read16: host_read16,
+            read32: host_read32,
+            write8: host_write8,
+            write16: host_write16,
+            write32: host_write32,
+            trap: host_trap,
+        }
+    }
+}
+
+/// Runs a self-contained block against `cpu`. As with arviss blocks, call `cpu.transfer()` afterwards to move to the
+/// next block.
+pub fn call_native(func: NativeBlockFunc, cpu: &mut Cpu) {
+    let mut regs = RegisterFile::from_cpu(cpu);
+    let host = unsafe { HostApi::for_cpu(cpu) };
+    func(&mut regs, &host);
+    regs.store(cpu);
+}
+
+#[inline]
+fn cpu_from<'a>(ctx: *mut c_void) -> &'a mut Cpu {
+    unsafe { &mut *(ctx as *mut Cpu) }
+}
+
+extern "C" fn host_read8(ctx: *mut c_void, addr: u32, value: &mut u32) -> bool {
+    cpu_from(ctx).read8(addr).map(|v| *value = v as u32).is_ok()
+}
+
+extern "C" fn host_read16(ctx: *mut c_void, addr: u32, value: &mut u32) -> bool {
+    cpu_from(ctx)
+        .read16(addr)
+        .map(|v| *value = v as u32)
+        .is_ok()
+}
+
+extern "C" fn host_read32(ctx: *mut c_void, addr: u32, value: &mut u32) -> bool {
+    cpu_from(ctx).read32(addr).map(|v| *value = v).is_ok()
+}
+
+extern "C" fn host_write8(ctx: *mut c_void, addr: u32, value: u32) -> bool {
+    cpu_from(ctx).write8(addr, value as u8).is_ok()
+}
+
+extern "C" fn host_write16(ctx: *mut c_void, addr: u32, value: u32) -> bool {
+    cpu_from(ctx).write16(addr, value as u16).is_ok()
+}
+
+extern "C" fn host_write32(ctx: *mut c_void, addr: u32, value: u32) -> bool {
+    cpu_from(ctx).write32(addr, value).is_ok()
+}
+
+extern "C" fn host_trap(ctx: *mut c_void, cause: u32, value: u32) {
+    let cpu = cpu_from(ctx);
+    match cause {
+        TRAP_ECALL => cpu.handle_ecall(),
+        TRAP_BREAKPOINT => cpu.handle_ebreak(),
+        TRAP_LOAD_ACCESS_FAULT => cpu.handle_trap(TrapCause::LoadAccessFault(value)),
+        TRAP_STORE_ACCESS_FAULT => cpu.handle_trap(TrapCause::StoreAccessFault(value)),
+        _ => cpu.handle_trap(TrapCause::IllegalInstruction(value)),
+    }
+}
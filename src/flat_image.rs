@@ -0,0 +1,174 @@
+// A minimal self-describing container for flat RV32 binaries.
+//
+// All fields are little-endian `u32`s.
+//
+// | Offset | Field       | Description                                                   |
+// |--------|-------------|---------------------------------------------------------------|
+// | 0      | magic       | The bytes `ARVF`.                                             |
+// | 4      | version     | Format version, currently 1.                                  |
+// | 8      | isa         | ISA flags. `ISA_RV32I` must be set; `ISA_RV32C` is optional.  |
+// | 12     | load_addr   | Address that the text is loaded at. Data follows immediately. |
+// | 16     | entry       | Address of the first instruction to execute.                  |
+// | 20     | text_len    | Number of bytes of code.                                      |
+// | 24     | data_len    | Number of bytes of initialized data.                          |
+// | 28     | bss_len     | Number of bytes of zero-initialized data following the data.  |
+//
+// The header is followed by `text_len` bytes of code, then `data_len` bytes of initialized data.
+
+use std::io::Write;
+use thiserror::Error;
+
+pub const FLAT_MAGIC: &[u8; 4] = b"ARVF";
+pub const FLAT_VERSION: u32 = 1;
+pub const FLAT_HEADER_SIZE: usize = 32;
+
+pub const ISA_RV32I: u32 = 1 << 0;
+pub const ISA_RV32C: u32 = 1 << 1;
+
+#[derive(Error, Debug)]
+pub enum FlatImageError {
+    #[error("not a flat image")]
+    BadMagic,
+
+    #[error("unsupported flat image version {version}")]
+    UnsupportedVersion { version: u32 },
+
+    #[error("unsupported ISA flags 0x{isa:08x}")]
+    UnsupportedIsa { isa: u32 },
+
+    #[error("flat image is truncated: expected {expected} bytes but found {actual}")]
+    Truncated { expected: usize, actual: usize },
+
+    #[error("failed to write flat image: {err}")]
+    WriteFailed {
+        #[from]
+        err: std::io::Error,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlatHeader {
+    pub version: u32,
+    pub isa: u32,
+    pub load_addr: u32,
+    pub entry: u32,
+    pub text_len: u32,
+    pub data_len: u32,
+    pub bss_len: u32,
+}
+
+impl FlatHeader {
+    pub fn is_compressed(&self) -> bool {
+        self.isa & ISA_RV32C != 0
+    }
+
+    /// The total size of the image, including its header.
+    pub fn file_size(&self) -> usize {
+        FLAT_HEADER_SIZE + self.text_len as usize + self.data_len as usize
+    }
+}
+
+/// A flat image whose text and data borrow from the bytes that it was read from.
+#[derive(Clone, Copy, Debug)]
+pub struct FlatImage<'a> {
+    pub header: FlatHeader,
+    pub text: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> FlatImage<'a> {
+    pub fn new(
+        isa: u32,
+        load_addr: u32,
+        entry: u32,
+        text: &'a [u8],
+        data: &'a [u8],
+        bss_len: u32,
+    ) -> Self {
+        Self {
+            header: FlatHeader {
+                version: FLAT_VERSION,
+                isa,
+                load_addr,
+                entry,
+                text_len: text.len() as u32,
+                data_len: data.len() as u32,
+                bss_len,
+            },
+            text,
+            data,
+        }
+    }
+
+    pub fn read(bytes: &'a [u8]) -> Result<Self, FlatImageError> {
+        if !bytes.starts_with(FLAT_MAGIC) {
+            return Err(FlatImageError::BadMagic);
+        }
+        if bytes.len() < FLAT_HEADER_SIZE {
+            return Err(FlatImageError::Truncated {
+                expected: FLAT_HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        let field = |index: usize| {
+            let offset = 4 + index * 4;
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let header = FlatHeader {
+            version: field(0),
+            isa: field(1),
+            load_addr: field(2),
+            entry: field(3),
+            text_len: field(4),
+            data_len: field(5),
+            bss_len: field(6),
+        };
+        if header.version != FLAT_VERSION {
+            return Err(FlatImageError::UnsupportedVersion {
+                version: header.version,
+            });
+        }
+        if header.isa & ISA_RV32I == 0 || header.isa & !(ISA_RV32I | ISA_RV32C) != 0 {
+            return Err(FlatImageError::UnsupportedIsa { isa: header.isa });
+        }
+        if bytes.len() < header.file_size() {
+            return Err(FlatImageError::Truncated {
+                expected: header.file_size(),
+                actual: bytes.len(),
+            });
+        }
+
+        let text_end = FLAT_HEADER_SIZE + header.text_len as usize;
+        let data_end = text_end + header.data_len as usize;
+        Ok(Self {
+            header,
+            text: &bytes[FLAT_HEADER_SIZE..text_end],
+            data: &bytes[text_end..data_end],
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), FlatImageError> {
+        let header = &self.header;
+        writer.write_all(FLAT_MAGIC)?;
+        for field in [
+            header.version,
+            header.isa,
+            header.load_addr,
+            header.entry,
+            header.text_len,
+            header.data_len,
+            header.bss_len,
+        ] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        writer.write_all(self.text)?;
+        writer.write_all(self.data)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use crate::block_finder::CodeRegion;
+use crate::flat_image::*;
+use arviss::backends::memory::basic::*;
+use arviss::Address;
+use std::ops::Range;
+use thiserror::Error;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const EF_RISCV_RVC: u32 = 0x0001;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+
+const SHN_UNDEF: usize = 0;
+const SHN_LORESERVE: usize = 0xff00; // Includes `SHN_XINDEX`, which would put the real index in section header 0.
+
+const STT_FUNC: u8 = 2;
+
+// Uninitialized data is zero filled this much at a time, so that its size needn't be trusted to allocate a buffer.
+const ZERO_FILL_CHUNK: usize = 4096;
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("unrecognized image format")]
+    UnknownFormat,
+
+    #[error("failed to read flat image: {err}")]
+    FlatImageFailed {
+        #[from]
+        err: FlatImageError,
+    },
+
+    #[error("not an ELF file")]
+    NotElf,
+
+    #[error("unsupported ELF file: {what}")]
+    Unsupported { what: &'static str },
+
+    #[error("image is truncated at offset 0x{offset:08x}")]
+    Truncated { offset: usize },
+
+    #[error("invalid segment at 0x{addr:08x}")]
+    InvalidSegment { addr: Address },
+
+    #[error("invalid section header string table index {index}")]
+    InvalidSectionIndex { index: usize },
+
+    #[error("failed to load image into memory at 0x{addr:08x}")]
+    LoadFailed { addr: Address },
+}
+
+/// A loadable segment of an image.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub addr: Address,       // Virtual address that the segment is loaded at.
+    pub data: Vec<u8>,       // Initialized contents of the segment.
+    pub mem_size: u32,       // Size in memory. Anything beyond `data` is zero filled.
+    pub is_executable: bool, // True if the segment contains code.
+    pub is_writable: bool,   // True if the segment contains data that the guest may modify.
+}
+
+impl Segment {
+    /// The range of addresses occupied by the segment in memory.
+    pub fn range(&self) -> Range<Address> {
+        self.addr..self.addr.wrapping_add(self.mem_size)
+    }
+}
+
+/// The kind of an allocated section, e.g., `.text`, `.data` or `.bss`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    Text,
+    ReadOnlyData,
+    Data,
+    Bss,
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub name: String,
+    pub addr: Address,
+    pub size: u32,
+    pub kind: SectionKind,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageSymbol {
+    pub name: String,
+    pub addr: Address,
+    pub size: u32,
+    pub is_function: bool,
+}
+
+/// An executable image, ready to be loaded into memory.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub entry: Address,
+    pub is_compressed: bool, // True if the image uses the compressed (C) extension.
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<ImageSymbol>,
+}
+
+impl Image {
+    /// Loads an ELF executable or a flat image, depending on what `bytes` contains.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        if bytes.starts_with(ELF_MAGIC) {
+            Self::from_elf(bytes)
+        } else if bytes.starts_with(FLAT_MAGIC) {
+            Self::from_flat(bytes)
+        } else {
+            Err(ImageError::UnknownFormat)
+        }
+    }
+
+    /// Loads a flat image. See `flat_image` for the format.
+    pub fn from_flat(bytes: &[u8]) -> Result<Self, ImageError> {
+        let flat = FlatImage::read(bytes)?;
+        let header = &flat.header;
+        let text_addr = header.load_addr;
+        let data_addr = text_addr.wrapping_add(header.text_len);
+        let bss_addr = data_addr.wrapping_add(header.data_len);
+        Ok(Self {
+            entry: header.entry,
+            is_compressed: header.is_compressed(),
+            segments: vec![
+                Segment {
+                    addr: text_addr,
+                    data: flat.text.to_vec(),
+                    mem_size: header.text_len,
+                    is_executable: true,
+                    is_writable: false,
+                },
+                Segment {
+                    addr: data_addr,
+                    data: flat.data.to_vec(),
+                    mem_size: header.data_len.wrapping_add(header.bss_len),
+                    is_executable: false,
+                    is_writable: true,
+                },
+            ],
+            sections: vec![
+                Section {
+                    name: ".text".to_string(),
+                    addr: text_addr,
+                    size: header.text_len,
+                    kind: SectionKind::Text,
+                },
+                Section {
+                    name: ".data".to_string(),
+                    addr: data_addr,
+                    size: header.data_len,
+                    kind: SectionKind::Data,
+                },
+                Section {
+                    name: ".bss".to_string(),
+                    addr: bss_addr,
+                    size: header.bss_len,
+                    kind: SectionKind::Bss,
+                },
+            ],
+            symbols: Vec::new(),
+        })
+    }
+
+    /// Parses a 32-bit little-endian RISC-V ELF executable.
+    pub fn from_elf(bytes: &[u8]) -> Result<Self, ImageError> {
+        let elf = Elf { bytes };
+        if bytes.len() < 52 || &bytes[0..4] != ELF_MAGIC {
+            return Err(ImageError::NotElf);
+        }
+        if bytes[4] != ELFCLASS32 {
+            return Err(ImageError::Unsupported { what: "not 32-bit" });
+        }
+        if bytes[5] != ELFDATA2LSB {
+            return Err(ImageError::Unsupported {
+                what: "not little-endian",
+            });
+        }
+        if elf.u16_at(16)? != ET_EXEC {
+            return Err(ImageError::Unsupported {
+                what: "not an executable",
+            });
+        }
+        if elf.u16_at(18)? != EM_RISCV {
+            return Err(ImageError::Unsupported { what: "not RISC-V" });
+        }
+
+        let entry = elf.u32_at(24)?;
+        let phoff = elf.u32_at(28)? as usize;
+        let shoff = elf.u32_at(32)? as usize;
+        let flags = elf.u32_at(36)?;
+        let phentsize = elf.u16_at(42)? as usize;
+        let phnum = elf.u16_at(44)? as usize;
+        let shentsize = elf.u16_at(46)? as usize;
+        let shnum = elf.u16_at(48)? as usize;
+        let shstrndx = elf.u16_at(50)? as usize;
+
+        // Program headers tell us what to load and where.
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            if elf.u32_at(ph)? != PT_LOAD {
+                continue;
+            }
+            let offset = elf.u32_at(ph + 4)? as usize;
+            let addr = elf.u32_at(ph + 8)?;
+            let file_size = elf.u32_at(ph + 16)? as usize;
+            let mem_size = elf.u32_at(ph + 20)?;
+            let p_flags = elf.u32_at(ph + 24)?;
+            if file_size > mem_size as usize || !fits_address_space(addr, mem_size) {
+                return Err(ImageError::InvalidSegment { addr });
+            }
+            segments.push(Segment {
+                addr,
+                data: elf.slice(offset, file_size)?.to_vec(),
+                mem_size,
+                is_executable: p_flags & PF_X != 0,
+                is_writable: p_flags & PF_W != 0,
+            });
+        }
+
+        // Section headers are optional, but if they're there then they give us `.text`, `.data`, `.bss` and symbols.
+        let mut sections = Vec::new();
+        let mut symbols = Vec::new();
+        if shoff != 0 && shnum != 0 {
+            if shstrndx == SHN_UNDEF || shstrndx >= SHN_LORESERVE || shstrndx >= shnum {
+                return Err(ImageError::InvalidSectionIndex { index: shstrndx });
+            }
+            let sh = |i: usize| shoff + i * shentsize;
+            let shstrtab = elf.u32_at(sh(shstrndx) + 16)? as usize;
+            for i in 0..shnum {
+                let base = sh(i);
+                let sh_name = elf.u32_at(base)? as usize;
+                let sh_type = elf.u32_at(base + 4)?;
+                let sh_flags = elf.u32_at(base + 8)?;
+                let sh_addr = elf.u32_at(base + 12)?;
+                let sh_offset = elf.u32_at(base + 16)? as usize;
+                let sh_size = elf.u32_at(base + 20)?;
+                let sh_link = elf.u32_at(base + 24)? as usize;
+
+                if sh_flags & SHF_ALLOC != 0 {
+                    let kind = if sh_flags & SHF_EXECINSTR != 0 {
+                        SectionKind::Text
+                    } else if sh_type == SHT_NOBITS {
+                        SectionKind::Bss
+                    } else if sh_flags & SHF_WRITE != 0 {
+                        SectionKind::Data
+                    } else {
+                        SectionKind::ReadOnlyData
+                    };
+                    sections.push(Section {
+                        name: elf.str_at(shstrtab + sh_name)?,
+                        addr: sh_addr,
+                        size: sh_size,
+                        kind,
+                    });
+                }
+
+                if sh_type == SHT_SYMTAB {
+                    let strtab = elf.u32_at(sh(sh_link) + 16)? as usize;
+                    for sym in (sh_offset..sh_offset + sh_size as usize).step_by(16) {
+                        let st_name = elf.u32_at(sym)? as usize;
+                        let st_info = elf.u8_at(sym + 12)?;
+                        let st_shndx = elf.u16_at(sym + 14)?;
+                        if st_name == 0 || st_shndx == 0 {
+                            // Skip anonymous and undefined symbols.
+                            continue;
+                        }
+                        symbols.push(ImageSymbol {
+                            name: elf.str_at(strtab + st_name)?,
+                            addr: elf.u32_at(sym + 4)?,
+                            size: elf.u32_at(sym + 8)?,
+                            is_function: st_info & 0xf == STT_FUNC,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            entry,
+            is_compressed: flags & EF_RISCV_RVC != 0,
+            segments,
+            sections,
+            symbols,
+        })
+    }
+
+    /// Copies the image's segments into memory at their virtual addresses, zero filling any uninitialized data. Fails
+    /// if a segment doesn't fit in the address space or in `mem`.
+    pub fn load_into(&self, mem: &mut BasicMem) -> Result<(), ImageError> {
+        for segment in &self.segments {
+            if !fits_address_space(segment.addr, segment.mem_size) {
+                return Err(ImageError::InvalidSegment { addr: segment.addr });
+            }
+            mem.write_bytes(segment.addr, &segment.data)
+                .map_err(|addr| ImageError::LoadFailed { addr })?;
+            let bss_size = (segment.mem_size as usize).saturating_sub(segment.data.len());
+            let bss_start = segment.addr.wrapping_add(segment.data.len() as u32);
+            let zeros = [0; ZERO_FILL_CHUNK];
+            for offset in (0..bss_size).step_by(ZERO_FILL_CHUNK) {
+                let len = ZERO_FILL_CHUNK.min(bss_size - offset);
+                mem.write_bytes(bss_start.wrapping_add(offset as u32), &zeros[..len])
+                    .map_err(|addr| ImageError::LoadFailed { addr })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The segments containing code, i.e., the only places that `BlockFinder` should look for instructions.
+    pub fn executable_segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter().filter(|s| s.is_executable)
+    }
+
+    /// The executable segment containing the entry point.
+    pub fn entry_segment(&self) -> Option<&Segment> {
+        self.executable_segments()
+            .find(|s| s.range().contains(&self.entry))
+    }
+
+    /// Places where execution might start, i.e., the entry point followed by any function symbols in the code regions,
+    /// which are worth giving to `BlockFinder::add_entry_points`.
+    pub fn entry_points(&self) -> Vec<Address> {
+        let mut functions: Vec<Address> = self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.is_function && symbol.addr != self.entry)
+            .map(|symbol| symbol.addr)
+            .filter(|&addr| {
+                self.code_regions()
+                    .iter()
+                    .any(|region| region.contains(addr))
+            })
+            .collect();
+        functions.sort_unstable();
+        functions.dedup();
+        std::iter::once(self.entry).chain(functions).collect()
+    }
+
+    /// The code regions of the image, which is what `BlockFinder` and `BlockWriter` should be given.
+    pub fn code_regions(&self) -> Vec<CodeRegion<'_>> {
+        self.executable_segments()
+            .map(|s| CodeRegion::new(s.addr, &s.data).with_writable(s.is_writable))
+            .collect()
+    }
+
+    /// The read-only data of the image, which `BlockFinder` can search for jump tables.
+    pub fn data_regions(&self) -> Vec<CodeRegion<'_>> {
+        self.segments
+            .iter()
+            .filter(|s| !s.is_executable && !s.is_writable)
+            .map(|s| CodeRegion::new(s.addr, &s.data))
+            .collect()
+    }
+
+    /// Looks up the address of a symbol by name.
+    pub fn symbol(&self, name: &str) -> Option<&ImageSymbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+}
+
+fn fits_address_space(addr: Address, size: u32) -> bool {
+    addr as u64 + size as u64 <= 1 << 32
+}
+
+// A bounds-checked view of the bytes of an ELF file.
+struct Elf<'a> {
+    bytes: &'a [u8],
+}
+
+impl Elf<'_> {
+    fn slice(&self, offset: usize, len: usize) -> Result<&[u8], ImageError> {
+        self.bytes
+            .get(offset..offset.saturating_add(len))
+            .ok_or(ImageError::Truncated { offset })
+    }
+
+    fn u8_at(&self, offset: usize) -> Result<u8, ImageError> {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16, ImageError> {
+        let bytes = self.slice(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, ImageError> {
+        let bytes = self.slice(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn str_at(&self, offset: usize) -> Result<String, ImageError> {
+        let bytes = self
+            .bytes
+            .get(offset..)
+            .ok_or(ImageError::Truncated { offset })?;
+        let len = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(ImageError::Truncated { offset })?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+}
@@ -0,0 +1,527 @@
+use crate::arviss_rlib::*;
+use crate::block_finder::*;
+use crate::block_writer::*;
+use crate::cfg::*;
+use crate::code_watch::*;
+use crate::library_cache::*;
+use crate::library_set::*;
+use arviss::platforms::basic::*;
+use arviss::Address;
+use libloading::{Library, Symbol};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use tempdir::TempDir;
+use thiserror::Error;
+
+pub type Cpu = Rv32iCpu<BasicMem>;
+pub type ArvissFunc = extern "C" fn(&mut Cpu);
+
+/// A block that adds its instructions to the budget, then returns the function for the block that comes next, if it
+/// knows it and the budget allows.
+pub type ChainedFunc = extern "C" fn(&mut Cpu, &mut Budget) -> NextFunc;
+
+/// Returns what the blocks in a library have done to code since it was last called. See `CodeChange::from_raw`.
+pub type TakeCodeChangeFunc = extern "C" fn() -> u64;
+
+/// Stops the blocks in a library from chaining to the block that starts at an address, or running it in a superblock.
+pub type DisableBlockFunc = extern "C" fn(Address);
+
+// Returning a `ChainedFunc` directly would make the type infinitely recursive, so break the cycle with a newtype.
+#[repr(transparent)]
+pub struct NextFunc(pub Option<ChainedFunc>);
+
+/// Counts the instructions that compiled code retires, and limits how many it can retire before giving control back to
+/// the host. The limit is only checked between blocks, so `retired` can go past it by up to one block. Matches the
+/// `Budget` in generated code.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub retired: u64,
+    pub limit: u64,
+}
+
+impl Budget {
+    /// A budget for `limit` instructions.
+    pub fn new(limit: u64) -> Self {
+        Self { retired: 0, limit }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(u64::MAX)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.retired >= self.limit
+    }
+
+    /// Allows `instructions` more instructions from wherever the budget has got to, e.g., for the next time slice.
+    pub fn extend(&mut self, instructions: u64) {
+        self.limit = self.retired.max(self.limit).saturating_add(instructions);
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// The counters behind the `cycle` and `instret` CSRs. They count the way that the interpreter runs: every instruction
+/// that is dispatched counts as retired, including one that traps, and takes one cycle. Native code has to count the
+/// same way, so that the counters don't depend on which tier ran what.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub cycle: u64,
+    pub instret: u64,
+}
+
+impl Counters {
+    pub fn retire(&mut self, instructions: u64) {
+        self.cycle += instructions;
+        self.instret += instructions;
+    }
+}
+
+/// The flags that generated code is compiled with, besides the ones that link it against arviss.
+pub const RUSTC_FLAGS: &[&str] = &[
+    "--edition=2021",
+    "--crate-type",
+    "cdylib",
+    "-Cpanic=abort",
+    "-C",
+    "opt-level=2",
+    "-C",
+    "strip=symbols",
+];
+
+/// Decides whether a compiled block should be loaded, given its index in the block list and the block itself.
+pub type BlockFilter = Box<dyn FnMut(usize, &Block) -> bool>;
+
+#[derive(Error, Debug)]
+pub enum JitError {
+    #[error("failed to find blocks: {err}")]
+    FindBlocksFailed {
+        #[from]
+        err: BlockFinderError,
+    },
+
+    #[error("failed to write blocks: {err}")]
+    WriteBlocksFailed {
+        #[from]
+        err: BlockWriterError,
+    },
+
+    #[error("i/o error: {err}")]
+    IoFailed {
+        #[from]
+        err: std::io::Error,
+    },
+
+    #[error("failed to locate arviss: {err}")]
+    ArvissNotFound {
+        #[from]
+        err: ArvissRlibError,
+    },
+
+    #[error("rustc failed: {status}")]
+    CompileFailed { status: ExitStatus },
+
+    #[error("failed to load library: {err}")]
+    LoadFailed {
+        #[from]
+        err: libloading::Error,
+    },
+
+    #[error("the compile worker has stopped")]
+    WorkerStopped,
+}
+
+/// A block that has been compiled and loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct CompiledBlock {
+    pub func: ArvissFunc,
+    pub chain: ChainedFunc, // Runs the block, then returns the next block's `chain` if it can.
+    pub take_change: TakeCodeChangeFunc, // From the library that the block is in.
+    pub disable: DisableBlockFunc, // Stops the library's other blocks from going to this one.
+    pub generation: Generation, // Of the library that the block is in.
+    pub block: Block,
+    pub instructions: u32, // The number of instructions in the block.
+}
+
+impl CompiledBlock {
+    /// Collects the latest write to code or `fence.i` by any block in this block's library. Blocks return as soon as
+    /// they do either, so there's at most one.
+    pub fn take_code_change(&self) -> Option<CodeChange> {
+        CodeChange::from_raw((self.take_change)())
+    }
+
+    /// Stops the other blocks in this block's library from going to it, so that control goes back to the host instead.
+    /// Must be called before the block is dropped, as the library may still be running the others.
+    pub fn unlink(&self) {
+        (self.disable)(self.block.start)
+    }
+
+    /// The number of instructions that a call to `func` retired, given the CPU after it transferred to the next pc and
+    /// the code change that the call reported. That's all of them, unless the block left early: after a trap, the pc is
+    /// the instruction that trapped, and after a write to code, it's the one after the store.
+    pub fn retired(&self, regions: &[CodeRegion], cpu: &Cpu, change: Option<CodeChange>) -> u32 {
+        let pc = cpu.pc();
+        let in_block = pc >= self.block.start && pc < self.block.end;
+        match (in_block, cpu.is_trapped(), change) {
+            (true, true, _) => count_instructions(regions, self.block.start, pc) + 1,
+            (true, false, Some(_)) => count_instructions(regions, self.block.start, pc),
+            _ => self.instructions,
+        }
+    }
+}
+
+/// Compiles basic blocks to native code via rustc and keeps the resulting libraries loaded for as long as any of their
+/// blocks are in the block map.
+pub struct BlockCompiler {
+    temp_dir: TempDir,
+    libs: LibrarySet,
+    block_map: HashMap<Address, CompiledBlock>,
+    filter: Option<BlockFilter>,
+    rejected: BTreeSet<Address>, // Starts of the blocks that the filter rejected, which are never compiled again.
+    arviss: Option<ArvissRlib>,
+    roots: BTreeSet<Address>, // Every address that blocks have been found from, including missed ones.
+    missed: BTreeSet<Address>, // Addresses that weren't in the block map, and haven't been compiled yet.
+    cache: Option<LibraryCache>,
+    superblocks: bool, // Whether to compile each function's blocks as one superblock.
+    code_watch: CodeWatch,
+}
+
+impl BlockCompiler {
+    pub fn new(dir: TempDir) -> Self {
+        Self {
+            temp_dir: dir,
+            libs: LibrarySet::default(),
+            block_map: HashMap::new(),
+            filter: None,
+            rejected: BTreeSet::new(),
+            arviss: None,
+            roots: BTreeSet::new(),
+            missed: BTreeSet::new(),
+            cache: None,
+            superblocks: false,
+            code_watch: CodeWatch::default(),
+        }
+    }
+
+    /// Creates a compiler that only loads the blocks accepted by `filter`. Useful for testing fallback to
+    /// interpreting. Chained blocks and superblocks don't go to the rejected blocks either, so they're always
+    /// interpreted.
+    pub fn with_filter(dir: TempDir, filter: impl FnMut(usize, &Block) -> bool + 'static) -> Self {
+        Self {
+            filter: Some(Box::new(filter)),
+            ..Self::new(dir)
+        }
+    }
+
+    /// Looks for compiled libraries in `cache` before running rustc, and adds the ones that it builds.
+    pub fn with_cache(mut self, cache: LibraryCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Compiles the new blocks in each function as a superblock, so that loops within a function run without leaving
+    /// native code. `run_chained` runs the superblocks, while `get` still returns the individual blocks.
+    pub fn with_superblocks(mut self, enabled: bool) -> Self {
+        self.superblocks = enabled;
+        self
+    }
+
+    pub fn get(&self, addr: Address) -> Option<&ArvissFunc> {
+        self.block_map.get(&addr).map(|compiled| &compiled.func)
+    }
+
+    pub fn compiled_block(&self, addr: Address) -> Option<&CompiledBlock> {
+        self.block_map.get(&addr)
+    }
+
+    /// The libraries that blocks in the block map were loaded from.
+    pub fn libraries(&self) -> &LibrarySet {
+        &self.libs
+    }
+
+    /// Runs compiled blocks starting from the one at `addr`, going directly from block to block where the blocks know
+    /// their successors, and looking up the rest. Stops when the CPU traps, the budget runs out, or it reaches an
+    /// address with no compiled block, and returns the address that it stopped at, so that the caller can resume from
+    /// there. Blocks that are made stale by writes to the code in `regions` are dropped along the way.
+    pub fn run_chained(
+        &mut self,
+        cpu: &mut Cpu,
+        budget: &mut Budget,
+        regions: &[CodeRegion],
+        addr: Address,
+    ) -> Address {
+        let Some(mut compiled) = self.block_map.get(&addr).copied() else {
+            return addr;
+        };
+        if budget.is_exhausted() {
+            return addr;
+        }
+        let mut func = compiled.chain;
+        loop {
+            if let NextFunc(Some(next)) = func(cpu, budget) {
+                func = next;
+                continue;
+            }
+
+            // Chains don't leave the library that they started in, so that's the one to ask about changes to code.
+            if let Some(change) = compiled.take_code_change() {
+                self.code_changed(change, cpu, regions);
+            }
+            let addr = cpu.pc();
+            match self.block_map.get(&addr) {
+                Some(next) if !cpu.is_trapped() && !budget.is_exhausted() => {
+                    compiled = *next;
+                    func = compiled.chain;
+                }
+                _ => return addr,
+            }
+        }
+    }
+
+    /// Drops any compiled blocks that `change` made stale, so that they're interpreted from now on, unloading the
+    /// libraries that no longer have any blocks. Returns the number of blocks dropped. This is only called between
+    /// calls into native code, so nothing is running in the libraries that it unloads.
+    pub fn code_changed(&mut self, change: CodeChange, cpu: &Cpu, regions: &[CodeRegion]) -> usize {
+        let stale = self.code_watch.apply(change, cpu, regions);
+        for start in &stale {
+            if let Some(compiled) = self.block_map.remove(start) {
+                compiled.unlink();
+                self.libs.release(compiled.generation);
+            }
+        }
+        self.libs.unload_unused();
+        stale.len()
+    }
+
+    /// Records that there was no compiled block for `addr`, so that `compile_missed` can compile it later. Addresses
+    /// that blocks have already been found from are ignored, as there's nothing more to be had from them.
+    pub fn record_miss(&mut self, addr: Address) {
+        if !self.roots.contains(&addr) && !self.block_map.contains_key(&addr) {
+            self.missed.insert(addr);
+        }
+    }
+
+    /// The number of missed addresses that are waiting to be compiled.
+    pub fn missed_count(&self) -> usize {
+        self.missed.len()
+    }
+
+    /// Compiles the code in `regions` that is reachable from `entry`.
+    pub fn compile(&mut self, regions: &[CodeRegion], entry: Address) -> Result<(), JitError> {
+        self.roots.insert(entry);
+        self.compile_new_blocks(regions)?;
+        Ok(())
+    }
+
+    /// Finds blocks from the addresses passed to `record_miss`, then compiles and loads any that aren't already in the
+    /// block map. Returns the number of blocks that were compiled.
+    pub fn compile_missed(&mut self, regions: &[CodeRegion]) -> Result<usize, JitError> {
+        // Missed addresses outside of the code can't be compiled, but they're remembered as roots anyway, so that they
+        // aren't recorded again.
+        let missed = std::mem::take(&mut self.missed);
+        self.roots.extend(missed);
+        self.compile_new_blocks(regions)
+    }
+
+    // Finds the blocks reachable from all of the roots, then compiles the ones that start at new addresses. Finding
+    // blocks from every root, rather than just the new ones, means that new blocks are split where they run into
+    // existing blocks. Existing blocks that get split are left alone, as running the whole of them is still correct.
+    fn compile_new_blocks(&mut self, regions: &[CodeRegion]) -> Result<usize, JitError> {
+        let roots: Vec<Address> = self
+            .roots
+            .iter()
+            .copied()
+            .filter(|&addr| regions.iter().any(|region| region.contains(addr)))
+            .collect();
+        let mut block_finder = BlockFinder::with_regions(regions.to_vec());
+        let blocks: Vec<Block> = block_finder
+            .add_entry_points(&roots)?
+            .into_iter()
+            .filter(|block| {
+                !self.block_map.contains_key(&block.start)
+                    && !self.rejected.contains(&block.start)
+                    && !self.code_watch.is_stale(block)
+            })
+            .collect();
+        if blocks.is_empty() {
+            return Ok(0);
+        }
+        let superblocks = self
+            .superblocks
+            .then(|| group_superblocks(&block_finder.cfg(), &blocks));
+        let superblocks = superblocks.as_deref();
+
+        // Each compilation gets its own module name, otherwise the loader would hand us back the library that it
+        // already has open.
+        let name = format!("demo_{}", self.libs.next_generation());
+        let arviss = match &self.arviss {
+            Some(arviss) => arviss,
+            None => self.arviss.insert(ArvissRlib::locate()?),
+        };
+        let library_path = match &mut self.cache {
+            Some(cache) => {
+                // Every entry's library has the same name, and another compiler may have the same entry open, so load
+                // a copy under this compilation's name. A link wouldn't do, as the loader recognizes the file.
+                let cached = cache.get_or_compile(regions, &blocks, superblocks, arviss)?;
+                let path = self
+                    .temp_dir
+                    .path()
+                    .join(libloading::library_filename(&name));
+                std::fs::copy(cached, &path)?;
+                path
+            }
+            None => compile_library(
+                self.temp_dir.path(),
+                &name,
+                regions,
+                &blocks,
+                superblocks,
+                arviss,
+            )?,
+        };
+
+        // Load the functions from the library, skipping any that the filter rejects. The rejected blocks are still in
+        // the library, so stop the others from chaining to them too.
+        let (generation, lib) = self.libs.load(library_path)?;
+        let chain_symbols = chain_symbols(&blocks, superblocks);
+        let mut block_map = HashMap::new();
+        for (index, block) in blocks.iter().enumerate() {
+            let chain_symbol = &chain_symbols[&block.start];
+            let compiled = load_block(lib, generation, regions, block, chain_symbol)?;
+            if let Some(filter) = &mut self.filter {
+                if !filter(index, block) {
+                    compiled.unlink();
+                    self.rejected.insert(block.start);
+                    continue;
+                }
+            }
+            block_map.insert(block.start, compiled);
+        }
+
+        // The library is owned by its blocks, and is unloaded straight away if there aren't any.
+        let compiled = block_map.len();
+        self.libs.retain(generation, compiled);
+        self.libs.unload_unused();
+        for compiled in block_map.values() {
+            self.code_watch.watch(&compiled.block);
+        }
+        self.block_map.extend(block_map);
+
+        Ok(compiled)
+    }
+}
+
+/// Writes `blocks` to a Rust module called `name` in `dir`, then compiles it with rustc. Returns the path of the
+/// library. If `superblocks` is given, it groups `blocks` into superblocks, which replace the chained blocks.
+pub(crate) fn compile_library(
+    dir: &Path,
+    name: &str,
+    regions: &[CodeRegion],
+    blocks: &[Block],
+    superblocks: Option<&[Vec<Block>]>,
+    arviss: &ArvissRlib,
+) -> Result<PathBuf, JitError> {
+    // Generate a Rust module containing source for each basic block.
+    let file_path = dir.join(format!("{name}.rs"));
+    let mut f = File::create(&file_path)?;
+    let mut block_writer = BlockWriter::with_regions(regions.to_vec(), Runtime::Arviss);
+    match superblocks {
+        Some(superblocks) => block_writer.write_superblocks(&mut f, superblocks)?,
+        None => block_writer.write_blocks(&mut f, blocks)?,
+    }
+    f.sync_all()?;
+
+    // Compile the module to a .so, linking against the same arviss that we were built with.
+    let mut command = Command::new("rustc");
+    let status = arviss
+        .add_args(&mut command)
+        .current_dir(dir)
+        .args(RUSTC_FLAGS)
+        .arg(&file_path)
+        .status()?;
+    if !status.success() {
+        return Err(JitError::CompileFailed { status });
+    }
+
+    Ok(dir.join(libloading::library_filename(name)))
+}
+
+/// Looks up the functions for `block` in a library built by `compile_library`, where `chain_symbol` is the function
+/// that `run_chained` should call for it, as given by `chain_symbols`.
+pub(crate) fn load_block(
+    lib: &Library,
+    generation: Generation,
+    regions: &[CodeRegion],
+    block: &Block,
+    chain_symbol: &str,
+) -> Result<CompiledBlock, JitError> {
+    let symbol = format!("block_{:08x}_{:08x}", block.start, block.end);
+    let basic_block_fn: Symbol<ArvissFunc> = unsafe { lib.get(symbol.as_bytes())? };
+    let chained_fn: Symbol<ChainedFunc> = unsafe { lib.get(chain_symbol.as_bytes())? };
+    let take_change_fn: Symbol<TakeCodeChangeFunc> = unsafe { lib.get(b"take_code_change")? };
+    let disable_fn: Symbol<DisableBlockFunc> = unsafe { lib.get(b"disable_block")? };
+    Ok(CompiledBlock {
+        func: *basic_block_fn,
+        chain: *chained_fn,
+        take_change: *take_change_fn,
+        disable: *disable_fn,
+        generation,
+        block: *block,
+        instructions: count_instructions(regions, block.start, block.end),
+    })
+}
+
+/// Names the function that runs each block and chains to the next, keyed by the block's start. That's the block's own
+/// chained function, or the superblock that it's in.
+pub(crate) fn chain_symbols(
+    blocks: &[Block],
+    superblocks: Option<&[Vec<Block>]>,
+) -> HashMap<Address, String> {
+    match superblocks {
+        Some(superblocks) => superblocks
+            .iter()
+            .flat_map(|superblock| {
+                let symbol = format!("superblock_{:08x}", superblock[0].start);
+                superblock
+                    .iter()
+                    .map(move |block| (block.start, symbol.clone()))
+            })
+            .collect(),
+        None => blocks
+            .iter()
+            .map(|block| {
+                let symbol = format!("chain_{:08x}_{:08x}", block.start, block.end);
+                (block.start, symbol)
+            })
+            .collect(),
+    }
+}
+
+// Groups blocks into a superblock per function, with the function's entry first if it's among them. Each block goes
+// into the first function that has it, so that the superblocks' names are unique, and any that aren't in a function
+// get a superblock of their own.
+fn group_superblocks(cfg: &Cfg, blocks: &[Block]) -> Vec<Vec<Block>> {
+    let mut ungrouped: BTreeMap<Address, Block> =
+        blocks.iter().map(|block| (block.start, *block)).collect();
+    let mut superblocks = Vec::new();
+    for function in cfg.functions() {
+        let mut superblock: Vec<Block> = ungrouped.remove(&function.entry).into_iter().collect();
+        for start in &function.blocks {
+            if let Some(block) = ungrouped.remove(start) {
+                superblock.push(block);
+            }
+        }
+        if !superblock.is_empty() {
+            superblocks.push(superblock);
+        }
+    }
+    superblocks.extend(ungrouped.into_values().map(|block| vec![block]));
+    superblocks
+}
@@ -1,5 +1,17 @@
+pub mod arviss_rlib;
 pub mod block_finder;
 pub mod block_writer;
+pub mod cfg;
+pub mod code_watch;
+pub mod compile_worker;
+pub mod differential;
 pub mod dll_api;
+pub mod flat_image;
+pub mod image;
+pub mod jit;
+pub mod library_cache;
+pub mod library_set;
+pub mod tiered;
 
-pub(crate) mod read_instruction;
\ No newline at end of file
+pub(crate) mod read_instruction;
+pub(crate) mod register_tracker;
\ No newline at end of file
@@ -0,0 +1,237 @@
+use crate::arviss_rlib::*;
+use crate::block_finder::*;
+use crate::block_writer::BLOCK_WRITER_VERSION;
+use crate::jit::*;
+use arviss::Address;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempdir::TempDir;
+use thiserror::Error;
+
+/// Environment variable that overrides where compiled libraries are cached.
+pub const LIBRARY_CACHE_ENV: &str = "LOAD_DLL_CACHE";
+
+// Every entry holds one library, built from a module of this name.
+const LIBRARY_NAME: &str = "blocks";
+
+// Lists the blocks in an entry's library, one per line, as "start end" in hex.
+const MANIFEST: &str = "manifest.txt";
+
+#[derive(Error, Debug)]
+pub enum LibraryCacheError {
+    #[error("nowhere to cache libraries; set LOAD_DLL_CACHE, XDG_CACHE_HOME or HOME")]
+    NoCacheDir,
+}
+
+/// An on-disk cache of compiled block libraries, so that running the same code again doesn't need rustc.
+///
+/// Entries are content-addressed. Each one is a directory named after a hash of everything that goes into its library:
+/// the code, the blocks and any superblocks that group them, the `BlockWriter` version, the rustc version and flags,
+/// and the contents of the arviss rlib. It holds the library, the source that it was compiled from, and a manifest of
+/// its blocks.
+#[derive(Clone, Debug)]
+pub struct LibraryCache {
+    dir: PathBuf,
+    rustc_version: Option<String>, // Asked for when it's first needed.
+    arviss_hash: Option<(PathBuf, u128)>, // The hash of an rlib's contents, from when it was first needed.
+}
+
+impl LibraryCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            rustc_version: None,
+            arviss_hash: None,
+        }
+    }
+
+    /// Uses the directory in `LOAD_DLL_CACHE` if it is set, otherwise `load_dll` in the user's cache directory.
+    pub fn locate() -> Result<Self, LibraryCacheError> {
+        if let Some(dir) = std::env::var_os(LIBRARY_CACHE_ENV) {
+            return Ok(Self::new(dir));
+        }
+        let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".cache"),
+                None => return Err(LibraryCacheError::NoCacheDir),
+            },
+        };
+        Ok(Self::new(cache_home.join("load_dll")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of the library containing `blocks` in the cache, compiling it and adding it to the cache first
+    /// if it isn't there.
+    pub(crate) fn get_or_compile(
+        &mut self,
+        regions: &[CodeRegion],
+        blocks: &[Block],
+        superblocks: Option<&[Vec<Block>]>,
+        arviss: &ArvissRlib,
+    ) -> Result<PathBuf, JitError> {
+        let key = self.key(regions, blocks, superblocks, arviss)?;
+        let entry = self.dir.join(key);
+        let library_path = entry.join(libloading::library_filename(LIBRARY_NAME));
+        let expected: Vec<(Address, Address)> = blocks
+            .iter()
+            .map(|block| (block.start, block.end))
+            .collect();
+        if entry.is_dir() {
+            if read_manifest(&entry).as_ref() == Some(&expected) {
+                return Ok(library_path);
+            }
+            // The entry is incomplete or, far less likely, its key collides with these blocks' key.
+            std::fs::remove_dir_all(&entry)?;
+        }
+
+        // Build the entry in a staging directory, then move it into place, so that other processes never see half of
+        // one. If another process gets there first then its entry is just as good, but any other failure to move it is
+        // an error.
+        std::fs::create_dir_all(&self.dir)?;
+        let staging = TempDir::new_in(&self.dir, "staging")?;
+        compile_library(
+            staging.path(),
+            LIBRARY_NAME,
+            regions,
+            blocks,
+            superblocks,
+            arviss,
+        )?;
+        write_manifest(staging.path(), blocks)?;
+        let staging = staging.into_path();
+        if let Err(err) = std::fs::rename(&staging, &entry) {
+            std::fs::remove_dir_all(&staging)?;
+            if read_manifest(&entry) != Some(expected) {
+                return Err(err.into());
+            }
+        }
+        Ok(library_path)
+    }
+
+    // Hashes everything that the library for `blocks` depends on.
+    fn key(
+        &mut self,
+        regions: &[CodeRegion],
+        blocks: &[Block],
+        superblocks: Option<&[Vec<Block>]>,
+        arviss: &ArvissRlib,
+    ) -> Result<String, JitError> {
+        let rustc_version = match &self.rustc_version {
+            Some(version) => version,
+            None => self.rustc_version.insert(rustc_version()?),
+        };
+
+        let mut hasher = Fnv128::default();
+        hasher.write_u32(BLOCK_WRITER_VERSION);
+        hasher.write(rustc_version.as_bytes());
+        hasher.write_u32(RUSTC_FLAGS.len() as u32);
+        for flag in RUSTC_FLAGS {
+            hasher.write(flag.as_bytes());
+        }
+
+        // The rlib is large, so its contents are only hashed once.
+        let arviss_hash = match &self.arviss_hash {
+            Some((rlib, hash)) if *rlib == arviss.rlib => *hash,
+            _ => {
+                let mut rlib_hasher = Fnv128::default();
+                rlib_hasher.write(&std::fs::read(&arviss.rlib)?);
+                let hash = rlib_hasher.finish();
+                self.arviss_hash = Some((arviss.rlib.clone(), hash));
+                hash
+            }
+        };
+        hasher.write(&arviss_hash.to_le_bytes());
+
+        hasher.write_u32(regions.len() as u32);
+        for region in regions {
+            hasher.write_u32(region.base);
+            hasher.write(region.mem);
+        }
+        hasher.write_u32(blocks.len() as u32);
+        for block in blocks {
+            hasher.write_u32(block.start);
+            hasher.write_u32(block.end);
+        }
+
+        // Chained blocks and superblocks are different libraries, even for the same blocks.
+        let superblocks = superblocks.unwrap_or_default();
+        hasher.write_u32(superblocks.len() as u32);
+        for superblock in superblocks {
+            hasher.write_u32(superblock.len() as u32);
+            for block in superblock {
+                hasher.write_u32(block.start);
+            }
+        }
+        Ok(format!("{:032x}", hasher.finish()))
+    }
+}
+
+fn rustc_version() -> Result<String, JitError> {
+    let output = Command::new("rustc").arg("-vV").output()?;
+    if !output.status.success() {
+        return Err(JitError::CompileFailed {
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn write_manifest(dir: &Path, blocks: &[Block]) -> std::io::Result<()> {
+    let manifest: String = blocks
+        .iter()
+        .map(|block| format!("{:08x} {:08x}\n", block.start, block.end))
+        .collect();
+    std::fs::write(dir.join(MANIFEST), manifest)
+}
+
+// Reads the starts and ends of the blocks listed in an entry's manifest.
+fn read_manifest(dir: &Path) -> Option<Vec<(Address, Address)>> {
+    let manifest = std::fs::read_to_string(dir.join(MANIFEST)).ok()?;
+    manifest
+        .lines()
+        .map(|line| {
+            let (start, end) = line.split_once(' ')?;
+            let start = Address::from_str_radix(start, 16).ok()?;
+            let end = Address::from_str_radix(end, 16).ok()?;
+            Some((start, end))
+        })
+        .collect()
+}
+
+// The 128-bit FNV-1a hash, which is stable across builds and platforms, unlike std's `DefaultHasher`.
+struct Fnv128(u128);
+
+impl Default for Fnv128 {
+    fn default() -> Self {
+        Self(0x6c62272e07bb014262b821756295c58d)
+    }
+}
+
+impl Fnv128 {
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    // Hashes the length first, so that consecutive fields can't run into each other.
+    fn write(&mut self, bytes: &[u8]) {
+        self.write_bytes(&(bytes.len() as u64).to_le_bytes());
+        self.write_bytes(bytes);
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u128;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u128 {
+        self.0
+    }
+}
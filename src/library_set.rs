@@ -0,0 +1,95 @@
+use crate::jit::JitError;
+use libloading::Library;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Numbers libraries in the order that they were loaded. Generations aren't reused, so a block's generation always
+/// names the library that it came from.
+pub type Generation = u64;
+
+/// The libraries that compiled blocks were loaded from.
+///
+/// Each library is owned by the blocks that were loaded from it. Once they have all been released, the library can be
+/// unloaded, but only when no native frame is running its code, and nothing will call a function copied out of it.
+/// The owner knows when that is, so it does the unloading.
+#[derive(Debug, Default)]
+pub struct LibrarySet {
+    next_generation: Generation,
+    loaded: BTreeMap<Generation, LoadedLibrary>,
+}
+
+#[derive(Debug)]
+struct LoadedLibrary {
+    lib: Library,
+    path: PathBuf,
+    blocks: usize, // Blocks loaded from the library that haven't been released.
+}
+
+impl LibrarySet {
+    /// Loads the library at `path`, which has no blocks until they're added with `retain`.
+    pub fn load(&mut self, path: impl Into<PathBuf>) -> Result<(Generation, &Library), JitError> {
+        let path = path.into();
+        let lib = unsafe { Library::new(&path)? };
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let loaded = self.loaded.entry(generation).or_insert(LoadedLibrary {
+            lib,
+            path,
+            blocks: 0,
+        });
+        Ok((generation, &loaded.lib))
+    }
+
+    /// The generation that the next library to be loaded will get.
+    pub fn next_generation(&self) -> Generation {
+        self.next_generation
+    }
+
+    pub fn get(&self, generation: Generation) -> Option<&Library> {
+        self.loaded.get(&generation).map(|loaded| &loaded.lib)
+    }
+
+    /// Records that `blocks` more blocks were loaded from the library.
+    pub fn retain(&mut self, generation: Generation, blocks: usize) {
+        if let Some(loaded) = self.loaded.get_mut(&generation) {
+            loaded.blocks += blocks;
+        }
+    }
+
+    /// Records that a block loaded from the library has gone. Returns true if it was the last one.
+    pub fn release(&mut self, generation: Generation) -> bool {
+        match self.loaded.get_mut(&generation) {
+            Some(loaded) => {
+                loaded.blocks = loaded.blocks.saturating_sub(1);
+                loaded.blocks == 0
+            }
+            None => false,
+        }
+    }
+
+    /// Unloads the library, whether or not it has blocks. Returns true if it was loaded.
+    pub fn unload(&mut self, generation: Generation) -> bool {
+        self.loaded.remove(&generation).is_some()
+    }
+
+    /// Unloads every library that has no blocks. Returns the number unloaded.
+    pub fn unload_unused(&mut self) -> usize {
+        let before = self.loaded.len();
+        self.loaded.retain(|_, loaded| loaded.blocks > 0);
+        before - self.loaded.len()
+    }
+
+    /// The number of libraries that are loaded.
+    pub fn len(&self) -> usize {
+        self.loaded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loaded.is_empty()
+    }
+
+    /// Where each loaded library was loaded from, oldest first.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.loaded.values().map(|loaded| loaded.path.as_path())
+    }
+}
@@ -0,0 +1,48 @@
+// The stable ABI between the host and blocks compiled with `Runtime::SelfContained`.
+//
+// This file is included verbatim by `dll_api.rs` and pasted into every self-contained generated library by
+// `BlockWriter`, so both sides always agree on the layout. It must not depend on anything outside of `core`. Bump
+// `ABI_VERSION` whenever anything in here changes.
+
+/// The version of the ABI. Self-contained libraries export it as `arviss_abi_version`.
+pub const ABI_VERSION: u32 = 1;
+
+// Trap causes passed to `HostApi::trap`. These are the RISC-V `mcause` exception codes.
+pub const TRAP_ILLEGAL_INSTRUCTION: u32 = 2;
+pub const TRAP_BREAKPOINT: u32 = 3;
+pub const TRAP_LOAD_ACCESS_FAULT: u32 = 5;
+pub const TRAP_STORE_ACCESS_FAULT: u32 = 7;
+pub const TRAP_ECALL: u32 = 11;
+
+/// The guest's registers, as seen by a self-contained block.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegisterFile {
+    pub x: [u32; 32],
+    pub next_pc: u32,
+}
+
+/// Reads from guest memory into `value`, returning false on an access fault.
+pub type ReadFunc = extern "C" fn(ctx: *mut core::ffi::c_void, addr: u32, value: &mut u32) -> bool;
+
+/// Writes `value` to guest memory, returning false on an access fault.
+pub type WriteFunc = extern "C" fn(ctx: *mut core::ffi::c_void, addr: u32, value: u32) -> bool;
+
+/// Raises a trap with one of the `TRAP_*` causes and an associated value, e.g., the faulting address.
+pub type TrapFunc = extern "C" fn(ctx: *mut core::ffi::c_void, cause: u32, value: u32);
+
+/// Callbacks into the host, passed to every self-contained block along with the opaque `ctx` that they expect.
+#[repr(C)]
+pub struct HostApi {
+    pub ctx: *mut core::ffi::c_void,
+    pub read8: ReadFunc,
+    pub read16: ReadFunc,
+    pub read32: ReadFunc,
+    pub write8: WriteFunc,
+    pub write16: WriteFunc,
+    pub write32: WriteFunc,
+    pub trap: TrapFunc,
+}
+
+/// The signature of a self-contained block.
+pub type NativeBlockFunc = extern "C" fn(&mut RegisterFile, &HostApi);
@@ -0,0 +1,225 @@
+// The runtime for blocks compiled with `Runtime::SelfContained`.
+//
+// This file is not part of the crate. `BlockWriter` pastes it into generated code after `native_abi.rs`. It mimics
+// just enough of arviss's CPU API for the generated instructions to compile unchanged, but forwards memory accesses and
+// traps to the host through `HostApi`.
+
+#[derive(Clone, Copy)]
+pub struct Reg(usize);
+
+impl Reg {
+    pub const ZERO: Reg = Reg(0);
+    pub const RA: Reg = Reg(1);
+    pub const SP: Reg = Reg(2);
+    pub const GP: Reg = Reg(3);
+    pub const TP: Reg = Reg(4);
+    pub const T0: Reg = Reg(5);
+    pub const T1: Reg = Reg(6);
+    pub const T2: Reg = Reg(7);
+    pub const S0: Reg = Reg(8);
+    pub const S1: Reg = Reg(9);
+    pub const A0: Reg = Reg(10);
+    pub const A1: Reg = Reg(11);
+    pub const A2: Reg = Reg(12);
+    pub const A3: Reg = Reg(13);
+    pub const A4: Reg = Reg(14);
+    pub const A5: Reg = Reg(15);
+    pub const A6: Reg = Reg(16);
+    pub const A7: Reg = Reg(17);
+    pub const S2: Reg = Reg(18);
+    pub const S3: Reg = Reg(19);
+    pub const S4: Reg = Reg(20);
+    pub const S5: Reg = Reg(21);
+    pub const S6: Reg = Reg(22);
+    pub const S7: Reg = Reg(23);
+    pub const S8: Reg = Reg(24);
+    pub const S9: Reg = Reg(25);
+    pub const S10: Reg = Reg(26);
+    pub const S11: Reg = Reg(27);
+    pub const T3: Reg = Reg(28);
+    pub const T4: Reg = Reg(29);
+    pub const T5: Reg = Reg(30);
+    pub const T6: Reg = Reg(31);
+}
+
+impl From<u32> for Reg {
+    fn from(value: u32) -> Self {
+        Reg(value as usize & 0x1f)
+    }
+}
+
+pub enum TrapCause {
+    IllegalInstruction(u32),
+    LoadAccessFault(u32),
+    StoreAccessFault(u32),
+}
+
+pub struct Cpu<'a> {
+    regs: &'a mut RegisterFile,
+    host: &'a HostApi,
+    trapped: bool, // Whether the block has raised a trap with the host.
+}
+
+impl<'a> Cpu<'a> {
+    #[inline(always)]
+    pub fn new(regs: &'a mut RegisterFile, host: &'a HostApi) -> Self {
+        Self {
+            regs,
+            host,
+            trapped: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn rx(&self, reg: Reg) -> u32 {
+        self.regs.x[reg.0]
+    }
+
+    #[inline(always)]
+    pub fn wx(&mut self, reg: Reg, value: u32) {
+        if reg.0 != 0 {
+            self.regs.x[reg.0] = value;
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_trapped(&self) -> bool {
+        self.trapped
+    }
+
+    #[inline(always)]
+    pub fn set_next_pc(&mut self, addr: u32) {
+        self.regs.next_pc = addr;
+    }
+
+    #[inline(always)]
+    fn read(&self, read: ReadFunc, addr: u32) -> Result<u32, u32> {
+        let mut value = 0;
+        if read(self.host.ctx, addr, &mut value) {
+            Ok(value)
+        } else {
+            Err(addr)
+        }
+    }
+
+    #[inline(always)]
+    fn write(&mut self, write: WriteFunc, addr: u32, value: u32) -> Result<(), u32> {
+        if write(self.host.ctx, addr, value) {
+            Ok(())
+        } else {
+            Err(addr)
+        }
+    }
+
+    pub fn read8(&self, addr: u32) -> Result<u8, u32> {
+        self.read(self.host.read8, addr).map(|value| value as u8)
+    }
+
+    pub fn read16(&self, addr: u32) -> Result<u16, u32> {
+        self.read(self.host.read16, addr).map(|value| value as u16)
+    }
+
+    pub fn read32(&self, addr: u32) -> Result<u32, u32> {
+        self.read(self.host.read32, addr)
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) -> Result<(), u32> {
+        self.write(self.host.write8, addr, value as u32)
+    }
+
+    pub fn write16(&mut self, addr: u32, value: u16) -> Result<(), u32> {
+        self.write(self.host.write16, addr, value as u32)
+    }
+
+    pub fn write32(&mut self, addr: u32, value: u32) -> Result<(), u32> {
+        self.write(self.host.write32, addr, value)
+    }
+
+    pub fn handle_trap(&mut self, cause: TrapCause) {
+        let (cause, value) = match cause {
+            TrapCause::IllegalInstruction(ins) => (TRAP_ILLEGAL_INSTRUCTION, ins),
+            TrapCause::LoadAccessFault(addr) => (TRAP_LOAD_ACCESS_FAULT, addr),
+            TrapCause::StoreAccessFault(addr) => (TRAP_STORE_ACCESS_FAULT, addr),
+        };
+        self.trapped = true;
+        (self.host.trap)(self.host.ctx, cause, value);
+    }
+
+    pub fn handle_ecall(&mut self) {
+        self.trapped = true;
+        (self.host.trap)(self.host.ctx, TRAP_ECALL, 0);
+    }
+
+    pub fn handle_ebreak(&mut self) {
+        self.trapped = true;
+        (self.host.trap)(self.host.ctx, TRAP_BREAKPOINT, 0);
+    }
+
+    // The RV32I instructions that the compact instructions expand to.
+
+    pub fn addi(&mut self, rd: Reg, rs1: Reg, iimm: u32) {
+        self.wx(rd, self.rx(rs1).wrapping_add(iimm));
+    }
+
+    pub fn andi(&mut self, rd: Reg, rs1: Reg, iimm: u32) {
+        self.wx(rd, self.rx(rs1) & iimm);
+    }
+
+    pub fn lui(&mut self, rd: Reg, uimm: u32) {
+        self.wx(rd, uimm);
+    }
+
+    pub fn add(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.wx(rd, self.rx(rs1).wrapping_add(self.rx(rs2)));
+    }
+
+    pub fn sub(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.wx(rd, self.rx(rs1).wrapping_sub(self.rx(rs2)));
+    }
+
+    pub fn xor(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.wx(rd, self.rx(rs1) ^ self.rx(rs2));
+    }
+
+    pub fn or(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.wx(rd, self.rx(rs1) | self.rx(rs2));
+    }
+
+    pub fn and(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.wx(rd, self.rx(rs1) & self.rx(rs2));
+    }
+
+    pub fn slli(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        self.wx(rd, self.rx(rs1) << shamt);
+    }
+
+    pub fn srli(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        self.wx(rd, self.rx(rs1) >> shamt);
+    }
+
+    pub fn srai(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        self.wx(rd, ((self.rx(rs1) as i32) >> shamt) as u32);
+    }
+
+    pub fn lw(&mut self, rd: Reg, rs1: Reg, iimm: u32) {
+        match self.read32(self.rx(rs1).wrapping_add(iimm)) {
+            Ok(word) => self.wx(rd, word),
+            Err(addr) => self.handle_trap(TrapCause::LoadAccessFault(addr)),
+        }
+    }
+
+    pub fn sw(&mut self, rs1: Reg, rs2: Reg, simm: u32) {
+        if let Err(addr) = self.write32(self.rx(rs1).wrapping_add(simm), self.rx(rs2)) {
+            self.handle_trap(TrapCause::StoreAccessFault(addr));
+        }
+    }
+
+    pub fn ebreak(&mut self) {
+        self.handle_ebreak();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn arviss_abi_version() -> u32 {
+    ABI_VERSION
+}
@@ -0,0 +1,131 @@
+use arviss::decoding::Reg;
+use arviss::Address;
+
+/// What is known about the value of a register.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Value {
+    #[default]
+    Unknown,
+    // The register holds a known constant, e.g., after `lui` / `addi`.
+    Const(u32),
+    // An unknown value that is below `limit`, e.g., after a bounds check.
+    Bounded {
+        limit: u32,
+    },
+    // The address of an entry in a table at `base`, indexed by an unknown value. The offset from `base` is below
+    // `limit` if it's known.
+    TableOffset {
+        base: Address,
+        limit: Option<u32>,
+    },
+    // A word loaded from an unknown entry of a table at `base`, which is `limit` bytes long if that's known.
+    TableEntry {
+        base: Address,
+        limit: Option<u32>,
+    },
+}
+
+/// Tracks the values of registers through a single basic block, so that jumps through registers can sometimes be
+/// resolved statically. Anything that isn't understood makes its destination register `Unknown`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RegisterTracker {
+    values: Vec<(Reg, Value)>, // Registers not in the list are `Unknown`.
+}
+
+impl RegisterTracker {
+    /// Forgets everything, e.g., at the start of a block.
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub(crate) fn get(&self, reg: Reg) -> Value {
+        if reg == Reg::ZERO {
+            return Value::Const(0);
+        }
+        self.values
+            .iter()
+            .find(|(r, _)| *r == reg)
+            .map_or(Value::Unknown, |&(_, value)| value)
+    }
+
+    pub(crate) fn set(&mut self, reg: Reg, value: Value) {
+        self.values.retain(|(r, _)| *r != reg);
+        if reg != Reg::ZERO && value != Value::Unknown {
+            self.values.push((reg, value));
+        }
+    }
+
+    /// Marks `reg` as holding an unknown value.
+    pub(crate) fn clobber(&mut self, reg: Reg) {
+        self.set(reg, Value::Unknown);
+    }
+
+    /// `rd = rs1 + imm`, which covers `addi`, `mv` and `li`.
+    pub(crate) fn add_immediate(&mut self, rd: Reg, rs1: Reg, imm: u32) {
+        let value = match self.get(rs1) {
+            Value::Const(value) => Value::Const(value.wrapping_add(imm)),
+            Value::TableOffset { base, limit } => Value::TableOffset {
+                base: base.wrapping_add(imm),
+                limit,
+            },
+            _ => Value::Unknown,
+        };
+        self.set(rd, value);
+    }
+
+    /// `rd = rs1 << shamt`, which is how an index is scaled to the size of a table's entries.
+    pub(crate) fn shift_left(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        let value = match self.get(rs1) {
+            Value::Const(value) => Value::Const(value.wrapping_shl(shamt)),
+            Value::Bounded { limit } if limit > 0 => {
+                let max = (limit - 1) as u64;
+                match (max << shamt.min(32)).checked_add(1) {
+                    Some(limit) if limit <= u32::MAX as u64 => Value::Bounded {
+                        limit: limit as u32,
+                    },
+                    _ => Value::Unknown,
+                }
+            }
+            _ => Value::Unknown,
+        };
+        self.set(rd, value);
+    }
+
+    /// `rd = rs1 + rs2`. Adding an unknown value to a constant is assumed to be indexing into a table.
+    pub(crate) fn add(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        let value = match (self.get(rs1), self.get(rs2)) {
+            (Value::Const(a), Value::Const(b)) => Value::Const(a.wrapping_add(b)),
+            (Value::Const(base), Value::Unknown) | (Value::Unknown, Value::Const(base)) => {
+                Value::TableOffset { base, limit: None }
+            }
+            (Value::Const(base), Value::Bounded { limit })
+            | (Value::Bounded { limit }, Value::Const(base)) => Value::TableOffset {
+                base,
+                limit: Some(limit),
+            },
+            _ => Value::Unknown,
+        };
+        self.set(rd, value);
+    }
+
+    /// `rd = mem[rs1 + imm]`, where `read_word` reads constant memory such as a jump table.
+    pub(crate) fn load_word(
+        &mut self,
+        rd: Reg,
+        rs1: Reg,
+        imm: u32,
+        read_word: impl Fn(Address) -> Option<u32>,
+    ) {
+        let value = match self.get(rs1) {
+            Value::Const(addr) => {
+                read_word(addr.wrapping_add(imm)).map_or(Value::Unknown, Value::Const)
+            }
+            Value::TableOffset { base, limit } => Value::TableEntry {
+                base: base.wrapping_add(imm),
+                limit,
+            },
+            _ => Value::Unknown,
+        };
+        self.set(rd, value);
+    }
+}
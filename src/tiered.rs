@@ -0,0 +1,176 @@
+use crate::block_finder::*;
+use crate::code_watch::*;
+use crate::compile_worker::*;
+use crate::jit::*;
+use arviss::{Address, DispatchRv32ic};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The number of times that a block is entered before it is compiled, unless told otherwise.
+pub const DEFAULT_HOT_THRESHOLD: u32 = 16;
+
+/// What a `TieredRuntime` has been up to.
+#[derive(Clone, Debug, Default)]
+pub struct TierStats {
+    pub interpreted_instructions: u64,
+    pub native_instructions: u64,
+    pub native_calls: u64,                      // Calls into compiled blocks.
+    pub blocks_compiled: usize, // Blocks compiled, including cold ones found along with hot ones.
+    pub blocks_invalidated: usize, // Compiled blocks dropped because their code was written to.
+    pub compile_time: Duration, // Time the caller spent finding, writing, compiling and loading blocks.
+    pub time_to_first_native: Option<Duration>, // Time from the start of `run` to the first call into a block.
+}
+
+// Where compiled blocks come from.
+enum Compiler {
+    Blocking(BlockCompiler),   // Interpreting stops while a block compiles.
+    Background(AsyncCompiler), // Interpreting carries on while a block compiles.
+}
+
+impl Compiler {
+    fn compiled_block(&self, addr: Address) -> Option<&CompiledBlock> {
+        match self {
+            Compiler::Blocking(compiler) => compiler.compiled_block(addr),
+            Compiler::Background(compiler) => compiler.compiled_block(addr),
+        }
+    }
+
+    fn code_changed(&mut self, change: CodeChange, cpu: &Cpu, regions: &[CodeRegion]) -> usize {
+        match self {
+            Compiler::Blocking(compiler) => compiler.code_changed(change, cpu, regions),
+            Compiler::Background(compiler) => compiler.code_changed(change, cpu),
+        }
+    }
+}
+
+/// Runs code in the interpreter, counting how many times each block is entered, and only compiles blocks once they
+/// become hot.
+pub struct TieredRuntime {
+    compiler: Compiler,
+    threshold: u32,
+    entry_counts: HashMap<Address, u32>,
+    stats: TierStats,
+    counters: Counters,
+}
+
+impl TieredRuntime {
+    pub fn new(compiler: BlockCompiler) -> Self {
+        Self::with_threshold(compiler, DEFAULT_HOT_THRESHOLD)
+    }
+
+    /// Creates a runtime that compiles a block when it has been entered `threshold` times.
+    pub fn with_threshold(compiler: BlockCompiler, threshold: u32) -> Self {
+        Self::with_compiler(Compiler::Blocking(compiler), threshold)
+    }
+
+    /// Creates a runtime that queues hot blocks for `compiler`'s worker, and keeps interpreting them until they have
+    /// been published. Only the time spent finding blocks to queue counts towards `compile_time`.
+    pub fn with_background_compiler(compiler: AsyncCompiler, threshold: u32) -> Self {
+        Self::with_compiler(Compiler::Background(compiler), threshold)
+    }
+
+    fn with_compiler(compiler: Compiler, threshold: u32) -> Self {
+        Self {
+            compiler,
+            threshold: threshold.max(1),
+            entry_counts: HashMap::new(),
+            stats: TierStats::default(),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &TierStats {
+        &self.stats
+    }
+
+    /// The guest's cycle and instruction counters, which are the same whichever tier ran each instruction.
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Runs `cpu` from its current pc until it traps, compiling hot blocks from the code in `regions`.
+    pub fn run(&mut self, cpu: &mut Cpu, regions: &[CodeRegion]) -> Result<(), JitError> {
+        let start = Instant::now();
+        let mut addr = cpu.pc();
+        while !cpu.is_trapped() {
+            match self.compiler.compiled_block(addr).copied() {
+                // Basic block compiled. Call the native code.
+                Some(compiled) => {
+                    self.stats
+                        .time_to_first_native
+                        .get_or_insert_with(|| start.elapsed());
+                    self.stats.native_calls += 1;
+                    (compiled.func)(cpu);
+                    let change = compiled.take_code_change();
+                    addr = cpu.transfer();
+                    let retired = compiled.retired(regions, cpu, change);
+                    self.stats.native_instructions += retired as u64;
+                    self.counters.retire(retired as u64);
+                    if let Some(change) = change {
+                        self.stats.blocks_invalidated +=
+                            self.compiler.code_changed(change, cpu, regions);
+                    }
+                }
+                // Basic block not compiled. Interpret until we get to one that is.
+                None => addr = self.interpret(cpu, regions)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Interprets until control reaches a compiled block or the CPU traps, and returns the address that it stopped at.
+    fn interpret(&mut self, cpu: &mut Cpu, regions: &[CodeRegion]) -> Result<Address, JitError> {
+        let mut next_in_sequence = None;
+        while !cpu.is_trapped() {
+            // Fetch.
+            let Ok(ins) = cpu.fetch() else {
+                break;
+            };
+
+            // Anything other than the next instruction in sequence was reached by a jump, so it starts a block.
+            let pc = cpu.pc();
+            if next_in_sequence != Some(pc) && self.enter_block(pc, regions)? {
+                return Ok(pc);
+            }
+
+            // Decode and dispatch, noting any change to code before the instruction changes the registers.
+            let change = CodeChange::before_interpreting(cpu, ins);
+            cpu.dispatch(ins);
+            self.stats.interpreted_instructions += 1;
+            self.counters.retire(1);
+            if let Some(change) = change.filter(|_| !cpu.is_trapped()) {
+                self.stats.blocks_invalidated += self.compiler.code_changed(change, cpu, regions);
+            }
+            next_in_sequence = Some(pc.wrapping_add(if (ins & 3) == 3 { 4 } else { 2 }));
+        }
+        Ok(cpu.pc())
+    }
+
+    // Counts an entry to the block at `addr`, compiling it when it becomes hot. Returns true if the block is compiled.
+    fn enter_block(&mut self, addr: Address, regions: &[CodeRegion]) -> Result<bool, JitError> {
+        // Pick up anything that the worker has finished, as this is the only chance to switch to native code.
+        if let Compiler::Background(compiler) = &mut self.compiler {
+            self.stats.blocks_compiled += compiler.poll()?;
+        }
+        if self.compiler.compiled_block(addr).is_some() {
+            return Ok(true);
+        }
+        let count = self.entry_counts.entry(addr).or_default();
+        *count += 1;
+        if *count == self.threshold {
+            let start = Instant::now();
+            match &mut self.compiler {
+                Compiler::Blocking(compiler) => {
+                    compiler.record_miss(addr);
+                    self.stats.blocks_compiled += compiler.compile_missed(regions)?;
+                }
+                Compiler::Background(compiler) => {
+                    compiler.record_miss(addr);
+                    compiler.queue_missed()?;
+                }
+            }
+            self.stats.compile_time += start.elapsed();
+        }
+        Ok(self.compiler.compiled_block(addr).is_some())
+    }
+}
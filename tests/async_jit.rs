@@ -0,0 +1,96 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README.
+
+mod common;
+
+use arviss::decoding::Reg;
+use arviss::DispatchRv32ic;
+use load_dll::block_finder::*;
+use load_dll::compile_worker::*;
+use load_dll::jit::*;
+use load_dll::tiered::*;
+
+use common::*;
+
+// Everything that the program can change.
+#[derive(Debug, PartialEq, Eq)]
+struct Outcome {
+    registers: Vec<u32>,
+    pc: u32,
+    trap: String,
+    result: u32,
+}
+
+fn outcome(cpu: &Cpu) -> Outcome {
+    Outcome {
+        registers: (0..32).map(|r| cpu.rx(Reg::from(r))).collect(),
+        pc: cpu.pc(),
+        trap: format!("{:?}", cpu.trap_cause()),
+        result: cpu.read32(RESULT_ADDR).expect("failed to read the result"),
+    }
+}
+
+fn interpreted(code: &[u8]) -> Outcome {
+    let mut cpu = new_cpu(code);
+    while !cpu.is_trapped() {
+        let Ok(ins) = cpu.fetch() else {
+            break;
+        };
+        cpu.dispatch(ins);
+    }
+    outcome(&cpu)
+}
+
+#[test]
+fn interpreter_runs_the_program() {
+    let code = program();
+    let outcome = interpreted(&code);
+    assert_eq!(outcome.result, 2 * 5050);
+    assert_eq!(outcome.registers[13], 1);
+}
+
+#[test]
+fn native_blocks_match_the_interpreter() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+
+    // Compile everything up front, so that nothing is interpreted.
+    let mut compiler =
+        AsyncCompiler::new(temp_dir("async_jit"), &regions).expect("failed to start");
+    assert!(compiler.queue(0).expect("failed to queue") > 0);
+    assert!(compiler.wait().expect("failed to compile") > 0);
+    assert_eq!(compiler.pending_count(), 0);
+
+    let mut cpu = new_cpu(&code);
+    let mut runtime = TieredRuntime::with_background_compiler(compiler, u32::MAX);
+    runtime.run(&mut cpu, &regions).expect("failed to run");
+    assert_eq!(runtime.stats().interpreted_instructions, 0);
+    assert_eq!(outcome(&cpu), interpreted(&code));
+}
+
+#[test]
+fn mixed_blocks_match_the_interpreter() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+
+    // Blocks are queued as soon as they're entered, and run natively once they arrive, so which ones get interpreted
+    // depends on how quickly rustc runs.
+    let compiler = AsyncCompiler::new(temp_dir("async_jit"), &regions).expect("failed to start");
+    let mut cpu = new_cpu(&code);
+    let mut runtime = TieredRuntime::with_background_compiler(compiler, 1);
+    runtime.run(&mut cpu, &regions).expect("failed to run");
+    assert_eq!(outcome(&cpu), interpreted(&code));
+}
+
+#[test]
+fn blocking_compiler_matches_the_interpreter() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+
+    // The loop is compiled on its second iteration, so it runs both ways.
+    let mut cpu = new_cpu(&code);
+    let mut runtime = TieredRuntime::with_threshold(BlockCompiler::new(temp_dir("async_jit")), 2);
+    runtime.run(&mut cpu, &regions).expect("failed to run");
+    assert!(runtime.stats().interpreted_instructions > 0);
+    assert!(runtime.stats().native_instructions > 0);
+    assert_eq!(outcome(&cpu), interpreted(&code));
+}
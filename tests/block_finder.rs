@@ -0,0 +1,148 @@
+mod common;
+
+use load_dll::block_finder::*;
+
+use common::*;
+
+fn code(instructions: &[u32]) -> Vec<u8> {
+    instructions
+        .iter()
+        .flat_map(|ins| ins.to_le_bytes())
+        .collect()
+}
+
+#[test]
+fn block_can_run_off_the_end_of_the_code() {
+    let code = code(&[addi(10, 0, 1), addi(10, 10, 1)]);
+    let cfg = BlockFinder::with_mem(&code)
+        .find_cfg(0)
+        .expect("failed to find blocks");
+    let blocks: Vec<(u32, u32)> = cfg.blocks().map(|block| (block.start, block.end)).collect();
+    assert_eq!(blocks, [(0, 8)]);
+    assert!(cfg.successors(0).is_empty());
+}
+
+#[test]
+fn block_can_run_off_the_end_of_a_region_before_another() {
+    // The first region ends in the middle of the code, and the second is somewhere else entirely.
+    let first = code(&[addi(10, 0, 1)]);
+    let second = code(&[EBREAK]);
+    let regions = vec![CodeRegion::new(0, &first), CodeRegion::new(0x1000, &second)];
+    let cfg = BlockFinder::with_regions(regions)
+        .find_cfg(0)
+        .expect("failed to find blocks");
+    let blocks: Vec<(u32, u32)> = cfg.blocks().map(|block| (block.start, block.end)).collect();
+    assert_eq!(blocks, [(0, 4)]);
+}
+
+#[test]
+fn block_that_falls_through_into_another_ends_there() {
+    // Both sides of the branch are found at the same time, and the not-taken side falls through into the taken side.
+    let code = code(&[bne(10, 0, 8), addi(10, 10, 1), addi(10, 10, 2), EBREAK]);
+    let cfg = BlockFinder::with_mem(&code)
+        .find_cfg(0)
+        .expect("failed to find blocks");
+    let blocks: Vec<(u32, u32)> = cfg.blocks().map(|block| (block.start, block.end)).collect();
+    assert_eq!(blocks, [(0, 4), (4, 8), (8, 16)]);
+    assert!(cfg.predecessors(8).iter().any(|edge| edge.from == 4));
+}
+
+const A0: u32 = 10;
+const A4: u32 = 14;
+const A5: u32 = 15;
+const HALT: u32 = 0x0000_006f; // jal x0, 0
+
+// A switch on a0 through a table of three cases, followed by code that isn't in it, and then by the table, which is
+// followed by more words that look like code addresses. `limit` and `bounds_check` branch to the default case at 0x30
+// when a0 is out of range.
+fn switch(limit: u32, bounds_check: u32) -> Vec<u8> {
+    code(&[
+        limit,             // 00
+        bounds_check,      // 04
+        addi(A4, 0, 0x40), // 08: table base
+        slli(A0, A0, 2),   // 0c
+        add(A0, A0, A4),   // 10
+        lw(A0, A0, 0),     // 14
+        jalr(0, A0, 0),    // 18
+        HALT,              // 1c: case 0
+        HALT,              // 20: case 1
+        HALT,              // 24: case 2
+        HALT,              // 28: not a case
+        HALT,              // 2c: not a case
+        HALT,              // 30: default
+        HALT,              // 34
+        HALT,              // 38
+        HALT,              // 3c
+        0x1c,              // 40: table
+        0x20,              // 44
+        0x24,              // 48
+        0x28,              // 4c: data that looks like code addresses
+        0x2c,              // 50
+    ])
+}
+
+fn block_starts(code: &[u8]) -> Vec<(u32, Confidence)> {
+    BlockFinder::with_mem(code)
+        .find_blocks(0)
+        .expect("failed to find blocks")
+        .iter()
+        .map(|block| (block.start, block.confidence))
+        .collect()
+}
+
+#[test]
+fn jump_table_ends_at_a_bltu_bounds_check() {
+    // if 2 < a0 goto default
+    let starts = block_starts(&switch(addi(A5, 0, 2), bltu(A5, A0, 0x2c)));
+    let expected: Vec<(u32, Confidence)> = [0, 8, 0x1c, 0x20, 0x24, 0x30]
+        .into_iter()
+        .map(|start| (start, Confidence::Reachable))
+        .collect();
+    assert_eq!(starts, expected);
+}
+
+#[test]
+fn jump_table_ends_at_a_bgeu_bounds_check() {
+    // if a0 >= 3 goto default
+    let starts = block_starts(&switch(addi(A5, 0, 3), bgeu(A0, A5, 0x2c)));
+    let expected: Vec<(u32, Confidence)> = [0, 8, 0x1c, 0x20, 0x24, 0x30]
+        .into_iter()
+        .map(|start| (start, Confidence::Reachable))
+        .collect();
+    assert_eq!(starts, expected);
+}
+
+#[test]
+fn jump_table_without_a_bounds_check_is_only_guessed() {
+    // The branch doesn't involve a0, so the size of the table isn't known. The first case is also where the jump would
+    // fall through to, so it's believed anyway.
+    let starts = block_starts(&switch(addi(A5, 0, 3), bgeu(A5, 0, 0x2c)));
+    use Confidence::*;
+    assert_eq!(
+        starts,
+        [
+            (0, Reachable),
+            (8, Reachable),
+            (0x1c, Reachable),
+            (0x20, Swept),
+            (0x24, Swept),
+            (0x28, Swept),
+            (0x2c, Swept),
+            (0x30, Reachable),
+        ]
+    );
+}
+
+#[test]
+fn jump_table_in_writable_memory_is_not_resolved() {
+    // The guest could patch the table, so the jump is indirect, and only its fall through is found.
+    let code = switch(addi(A5, 0, 2), bltu(A5, A0, 0x2c));
+    let regions = vec![CodeRegion::new(0, &code).with_writable(true)];
+    let starts: Vec<u32> = BlockFinder::with_regions(regions)
+        .find_blocks(0)
+        .expect("failed to find blocks")
+        .iter()
+        .map(|block| block.start)
+        .collect();
+    assert_eq!(starts, [0, 8, 0x1c, 0x30]);
+}
@@ -0,0 +1,58 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README.
+
+mod common;
+
+use load_dll::block_finder::*;
+use load_dll::jit::*;
+
+use common::*;
+
+// The start of the loop in `program`, which the block before it falls into.
+const LOOP: u32 = 0x08;
+
+// Far more than the program needs, so that a runaway guest fails the test rather than hanging it.
+const MAX_INSTRUCTIONS: u64 = 10_000;
+
+// Runs `program` with the loop's block filtered out, and returns where the compiled code stopped.
+fn run_without_the_loop(superblocks: bool) -> u32 {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+    let compiler = BlockCompiler::with_filter(temp_dir("chaining"), |_, block| block.start != LOOP);
+    let mut compiler = compiler.with_superblocks(superblocks);
+    compiler.compile(&regions, 0).expect("failed to compile");
+    assert!(compiler.get(0).is_some());
+    assert!(compiler.get(LOOP).is_none());
+
+    let mut cpu = new_cpu(&code);
+    let mut budget = Budget::new(MAX_INSTRUCTIONS);
+    compiler.run_chained(&mut cpu, &mut budget, &regions, 0)
+}
+
+#[test]
+fn chained_block_does_not_go_to_a_filtered_block() {
+    assert_eq!(run_without_the_loop(false), LOOP);
+}
+
+#[test]
+fn superblock_does_not_run_a_filtered_block() {
+    assert_eq!(run_without_the_loop(true), LOOP);
+}
+
+#[test]
+fn filtered_block_is_not_compiled_again() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+    let mut compiler =
+        BlockCompiler::with_filter(temp_dir("chaining"), |_, block| block.start != LOOP);
+    compiler.compile(&regions, 0).expect("failed to compile");
+
+    // Missing the block, as the interpreter would, finds it again, but it isn't compiled or loaded.
+    compiler.record_miss(LOOP);
+    assert_eq!(
+        compiler
+            .compile_missed(&regions)
+            .expect("failed to compile"),
+        0
+    );
+    assert!(compiler.get(LOOP).is_none());
+}
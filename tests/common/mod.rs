@@ -0,0 +1,113 @@
+// Shared by the tests that compile blocks with rustc. Not every test uses everything.
+#![allow(dead_code)]
+
+use arviss::platforms::basic::*;
+use load_dll::jit::*;
+use tempdir::TempDir;
+
+pub const RESULT_ADDR: u32 = 0x100;
+
+pub fn i_type(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+pub fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0x13, 0, rd, rs1, imm)
+}
+
+pub fn slli(rd: u32, rs1: u32, shamt: i32) -> u32 {
+    i_type(0x13, 1, rd, rs1, shamt)
+}
+
+pub fn jalr(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0x67, 0, rd, rs1, imm)
+}
+
+pub fn add(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (rs2 << 20) | (rs1 << 15) | (rd << 7) | 0x33
+}
+
+pub fn sw(rs2: u32, rs1: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5 & 0x7f) << 25) | (rs2 << 20) | (rs1 << 15) | (2 << 12) | ((imm & 0x1f) << 7) | 0x23
+}
+
+pub fn lui(rd: u32, imm: u32) -> u32 {
+    (imm & 0xffff_f000) | (rd << 7) | 0x37
+}
+
+pub fn lw(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(0x03, 2, rd, rs1, imm)
+}
+
+pub fn branch(funct3: u32, rs1: u32, rs2: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    ((imm >> 12 & 1) << 31)
+        | ((imm >> 5 & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm >> 1 & 0xf) << 8)
+        | ((imm >> 11 & 1) << 7)
+        | 0x63
+}
+
+pub fn bne(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    branch(1, rs1, rs2, offset)
+}
+
+pub fn bltu(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    branch(6, rs1, rs2, offset)
+}
+
+pub fn bgeu(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    branch(7, rs1, rs2, offset)
+}
+
+pub fn jal(rd: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    ((imm >> 20 & 1) << 31)
+        | ((imm >> 1 & 0x3ff) << 21)
+        | ((imm >> 11 & 1) << 20)
+        | ((imm >> 12 & 0xff) << 12)
+        | (rd << 7)
+        | 0x6f
+}
+
+pub const EBREAK: u32 = 0x0010_0073;
+
+// Sums 1..=100 in a loop, then calls a function that doubles the sum and counts its calls, stores the result, and
+// stops.
+pub fn program() -> Vec<u8> {
+    let (ra, a0, a1, a2, a3) = (1, 10, 11, 12, 13);
+    [
+        addi(a0, 0, 0),                // 00
+        addi(a1, 0, 100),              // 04
+        add(a0, a0, a1),               // 08: loop
+        addi(a1, a1, -1),              // 0c
+        bne(a1, 0, -8),                // 10: to loop
+        jal(ra, 12),                   // 14: to double
+        sw(a2, 0, RESULT_ADDR as i32), // 18
+        EBREAK,                        // 1c
+        slli(a2, a0, 1),               // 20: double
+        addi(a3, a3, 1),               // 24
+        jalr(0, ra, 0),                // 28
+    ]
+    .iter()
+    .flat_map(|ins| ins.to_le_bytes())
+    .collect()
+}
+
+pub fn new_cpu(code: &[u8]) -> Cpu {
+    let mut mem = BasicMem::new();
+    mem.write_bytes(0, code)
+        .expect("failed to load the program");
+    let mut cpu = Cpu::with_mem(mem);
+    cpu.set_next_pc(0);
+    cpu.transfer();
+    cpu
+}
+
+pub fn temp_dir(prefix: &str) -> TempDir {
+    TempDir::new(prefix).expect("failed to create a temporary directory")
+}
@@ -0,0 +1,102 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README.
+
+mod common;
+
+use arviss::platforms::basic::*;
+use arviss::DispatchRv32ic;
+use load_dll::image::*;
+use load_dll::jit::*;
+use load_dll::tiered::*;
+
+use common::*;
+
+// The flat image wraps `hello_world.rv32ic`.
+const IMAGE: &str = "images/hello_world_rv32ic.flat";
+
+// Far more than hello world needs, so that a runaway guest fails the test rather than hanging it.
+const MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+fn load_image() -> Image {
+    let bytes = std::fs::read(IMAGE).expect("failed to read the image");
+    Image::from_bytes(&bytes).expect("failed to parse the image")
+}
+
+fn new_cpu(image: &Image) -> Cpu {
+    let mut mem = BasicMem::new();
+    image.load_into(&mut mem).expect("failed to load the image");
+    let mut cpu = Cpu::with_mem(mem);
+    cpu.set_next_pc(image.entry);
+    cpu.transfer();
+    cpu
+}
+
+// What the counters should be: the interpreter, on its own, counting every instruction that it dispatches.
+fn interpreted(image: &Image) -> Counters {
+    let mut cpu = new_cpu(image);
+    let mut counters = Counters::default();
+    while !cpu.is_trapped() && counters.instret < MAX_INSTRUCTIONS {
+        let Ok(ins) = cpu.fetch() else {
+            break;
+        };
+        cpu.dispatch(ins);
+        counters.retire(1);
+    }
+    assert!(cpu.is_trapped(), "hello world didn't finish");
+    counters
+}
+
+#[test]
+fn tiered_interpreter_counts_like_the_interpreter() {
+    let image = load_image();
+    let regions = image.code_regions();
+    let mut cpu = new_cpu(&image);
+    let mut runtime =
+        TieredRuntime::with_threshold(BlockCompiler::new(temp_dir("counters")), u32::MAX);
+    runtime.run(&mut cpu, &regions).expect("failed to run");
+    assert_eq!(runtime.stats().native_instructions, 0);
+    assert_eq!(*runtime.counters(), interpreted(&image));
+}
+
+#[test]
+fn compiled_blocks_count_like_the_interpreter() {
+    let image = load_image();
+    let regions = image.code_regions();
+    let mut compiler = BlockCompiler::new(temp_dir("counters"));
+    compiler
+        .compile(&regions, image.entry)
+        .expect("failed to compile");
+
+    // Run the way that `write_blocks` does, compiling anything that was missed, e.g., the targets of indirect jumps.
+    let mut cpu = new_cpu(&image);
+    let mut budget = Budget::new(MAX_INSTRUCTIONS);
+    let mut addr = image.entry;
+    while !cpu.is_trapped() && !budget.is_exhausted() {
+        if compiler.get(addr).is_none() {
+            compiler.record_miss(addr);
+            compiler
+                .compile_missed(&regions)
+                .expect("failed to compile");
+        }
+        assert!(compiler.get(addr).is_some(), "no code at 0x{addr:08x}");
+        addr = compiler.run_chained(&mut cpu, &mut budget, &regions, addr);
+    }
+    let mut counters = Counters::default();
+    counters.retire(budget.retired);
+    assert_eq!(counters, interpreted(&image));
+}
+
+#[test]
+fn fallback_counts_like_the_interpreter() {
+    let image = load_image();
+    let regions = image.code_regions();
+
+    // Compile every block as soon as it's entered, but leave some out, as `fallback` does, so that they're always
+    // interpreted.
+    let compiler = BlockCompiler::with_filter(temp_dir("counters"), |index, _| index % 8 != 7);
+    let mut cpu = new_cpu(&image);
+    let mut runtime = TieredRuntime::with_threshold(compiler, 1);
+    runtime.run(&mut cpu, &regions).expect("failed to run");
+    assert!(runtime.stats().interpreted_instructions > 0);
+    assert!(runtime.stats().native_instructions > 0);
+    assert_eq!(*runtime.counters(), interpreted(&image));
+}
@@ -0,0 +1,176 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README.
+
+mod common;
+
+use arviss::platforms::basic::*;
+use load_dll::block_finder::*;
+use load_dll::differential::*;
+use load_dll::image::*;
+use load_dll::jit::*;
+
+use common::*;
+
+const IMAGE: &str = "images/hello_world_rv32ic.flat";
+
+fn c_li(rd: u32, imm: i32) -> u16 {
+    let imm = imm as u32;
+    (0x4001 | ((imm & 0x20) << 7) | (rd << 7) | ((imm & 0x1f) << 2)) as u16
+}
+
+fn c_addi(rd: u32, imm: i32) -> u16 {
+    let imm = imm as u32;
+    (0x0001 | ((imm & 0x20) << 7) | (rd << 7) | ((imm & 0x1f) << 2)) as u16
+}
+
+fn c_mv(rd: u32, rs2: u32) -> u16 {
+    (0x8002 | (rd << 7) | (rs2 << 2)) as u16
+}
+
+fn c_jr(rs1: u32) -> u16 {
+    (0x8002 | (rs1 << 7)) as u16
+}
+
+fn c_jalr(rs1: u32) -> u16 {
+    (0x9002 | (rs1 << 7)) as u16
+}
+
+fn c_jal(offset: i32) -> u16 {
+    let imm = offset as u32;
+    (0x2001
+        | ((imm >> 11 & 1) << 12)
+        | ((imm >> 4 & 1) << 11)
+        | ((imm >> 8 & 3) << 9)
+        | ((imm >> 10 & 1) << 8)
+        | ((imm >> 6 & 1) << 7)
+        | ((imm >> 7 & 1) << 6)
+        | ((imm >> 1 & 7) << 3)
+        | ((imm >> 5 & 1) << 2)) as u16
+}
+
+// Only for the compact registers, x8 to x15.
+fn c_lw(rd: u32, rs1: u32, offset: u32) -> u16 {
+    (0x4000
+        | ((offset >> 3 & 7) << 10)
+        | ((rs1 - 8) << 7)
+        | ((offset >> 2 & 1) << 6)
+        | ((offset >> 6 & 1) << 5)
+        | ((rd - 8) << 2)) as u16
+}
+
+const C_NOP: u16 = 0x0001;
+const C_EBREAK: u16 = 0x9002;
+
+enum Ins {
+    Compact(u16),
+    Regular(u32),
+}
+
+// Calls a function with c.jal, then jumps through ra with c.jalr, which links to ra too, then loads from an address
+// that isn't mapped, and returns.
+fn compact_program() -> Vec<u8> {
+    let (ra, a0, a1, a2, a3, a4) = (1, 10, 11, 12, 13, 14);
+    use Ins::*;
+    [
+        Compact(c_li(a0, 5)),                   // 00
+        Compact(c_jal(10)),                     // 02: to increment
+        Compact(c_li(ra, 0x14)),                // 04
+        Compact(c_jalr(ra)),                    // 06: to 14
+        Compact(C_EBREAK),                      // 08
+        Compact(C_NOP),                         // 0a
+        Compact(c_addi(a0, 1)),                 // 0c: increment
+        Compact(c_jr(ra)),                      // 0e
+        Compact(C_NOP),                         // 10
+        Compact(C_NOP),                         // 12
+        Compact(c_mv(a1, ra)),                  // 14
+        Regular(sw(a1, 0, RESULT_ADDR as i32)), // 16
+        Regular(lui(a3, 0xffff_f000)),          // 1a
+        Compact(c_lw(a2, a3, 0)),               // 1e: faults
+        Compact(c_li(a4, 1)),                   // 20
+        Compact(c_jr(ra)),                      // 22: to 08
+    ]
+    .iter()
+    .flat_map(|ins| match ins {
+        Compact(ins) => ins.to_le_bytes().to_vec(),
+        Regular(ins) => ins.to_le_bytes().to_vec(),
+    })
+    .collect()
+}
+
+fn find_blocks(regions: &[CodeRegion], entry_points: &[u32]) -> Vec<Block> {
+    BlockFinder::with_regions(regions.to_vec())
+        .add_entry_points(entry_points)
+        .expect("failed to find blocks")
+}
+
+fn assert_no_divergences(divergences: &[Divergence]) {
+    let report: Vec<String> = divergences.iter().map(Divergence::to_string).collect();
+    assert!(divergences.is_empty(), "{}", report.join("\n"));
+}
+
+#[test]
+fn program_matches_the_interpreter() {
+    let code = program();
+    let regions = vec![CodeRegion::new(0, &code)];
+    let blocks = find_blocks(&regions, &[0]);
+    let mut tester = DifferentialTester::new(temp_dir("differential"), regions);
+    let divergences = tester
+        .check(&blocks, || new_cpu(&code))
+        .expect("failed to check");
+    assert_no_divergences(&divergences);
+}
+
+#[test]
+fn compact_program_matches_the_interpreter() {
+    let code = compact_program();
+    let regions = vec![CodeRegion::new(0, &code)];
+    let blocks = find_blocks(&regions, &[0, 0x14]);
+    let mut tester = DifferentialTester::new(temp_dir("differential"), regions);
+    let divergences = tester
+        .check(&blocks, || new_cpu(&code))
+        .expect("failed to check");
+    assert_no_divergences(&divergences);
+}
+
+#[test]
+fn hello_world_matches_the_interpreter() {
+    let bytes = std::fs::read(IMAGE).expect("failed to read the image");
+    let image = Image::from_bytes(&bytes).expect("failed to parse the image");
+    let regions = image.code_regions();
+    let blocks = find_blocks(&regions, &[image.entry]);
+    let mut tester = DifferentialTester::new(temp_dir("differential"), regions);
+    let setup = || {
+        let mut mem = BasicMem::new();
+        image.load_into(&mut mem).expect("failed to load the image");
+        Cpu::with_mem(mem)
+    };
+    let divergences = tester.check(&blocks, setup).expect("failed to check");
+    assert_no_divergences(&divergences);
+}
+
+#[test]
+fn reports_the_first_divergent_instruction() {
+    // Compile the program, but interpret one where the second instruction sets a1 to 99 rather than 100, so that the
+    // block containing it diverges there.
+    let code = program();
+    let mut changed = code.clone();
+    changed[4..8].copy_from_slice(&addi(11, 0, 99).to_le_bytes());
+    let regions = vec![CodeRegion::new(0, &code)];
+    let blocks = find_blocks(&regions, &[0]);
+    let mut tester = DifferentialTester::new(temp_dir("differential"), regions);
+    let divergences = tester
+        .check(&blocks, || new_cpu(&changed))
+        .expect("failed to check");
+    assert_eq!(divergences.len(), 1);
+    let divergence = &divergences[0];
+    assert_eq!(divergence.block.start, 0);
+    let instruction = divergence
+        .instruction
+        .as_ref()
+        .expect("no divergent instruction");
+    assert_eq!(instruction.addr, 4);
+    assert_eq!(instruction.ins, addi(11, 0, 100));
+    assert_eq!(
+        divergence.differences,
+        ["x11: native 0x00000064, interpreted 0x00000063"]
+    );
+}
@@ -0,0 +1,189 @@
+use arviss::platforms::basic::*;
+use load_dll::image::*;
+
+const ENTRY: u32 = 0x1000;
+const EBREAK: u32 = 0x0010_0073;
+
+// Builds a minimal RV32 executable: the ELF header, one loadable segment holding an `ebreak` at the entry point, and
+// a null section header followed by a section header string table.
+fn minimal_elf() -> Vec<u8> {
+    let mut elf = vec![0; 52];
+    elf[0..4].copy_from_slice(b"\x7fELF");
+    elf[4] = 1; // 32-bit.
+    elf[5] = 1; // Little-endian.
+    elf[6] = 1; // Version.
+    elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // Executable.
+    elf[18..20].copy_from_slice(&243u16.to_le_bytes()); // RISC-V.
+    elf[24..28].copy_from_slice(&ENTRY.to_le_bytes());
+    elf[28..32].copy_from_slice(&52u32.to_le_bytes()); // Program headers follow the ELF header.
+    elf[42..44].copy_from_slice(&32u16.to_le_bytes());
+    elf[44..46].copy_from_slice(&1u16.to_le_bytes());
+
+    // The program header, then the code.
+    let code_offset = 52 + 32;
+    let mut ph = [0u32; 8];
+    ph[0] = 1; // Loadable.
+    ph[1] = code_offset;
+    ph[2] = ENTRY;
+    ph[4] = 4; // File size.
+    ph[5] = 4; // Memory size.
+    ph[6] = 5; // Readable and executable.
+    elf.extend(ph.iter().flat_map(|word| word.to_le_bytes()));
+    elf.extend(EBREAK.to_le_bytes());
+
+    // The section header string table, then the section headers.
+    let shstrtab_offset = elf.len() as u32;
+    elf.extend(b"\0.shstrtab\0");
+    let shoff = elf.len() as u32;
+    elf.extend([0; 40]);
+    let mut sh = [0u32; 10];
+    sh[0] = 1; // Name.
+    sh[1] = 3; // String table.
+    sh[4] = shstrtab_offset;
+    sh[5] = 11;
+    elf.extend(sh.iter().flat_map(|word| word.to_le_bytes()));
+    elf[32..36].copy_from_slice(&shoff.to_le_bytes());
+    elf[46..48].copy_from_slice(&40u16.to_le_bytes());
+    elf[48..50].copy_from_slice(&2u16.to_le_bytes());
+    elf[50..52].copy_from_slice(&1u16.to_le_bytes());
+    elf
+}
+
+// Offsets of the program header's fields.
+const PH_FILE_SIZE: usize = 52 + 16;
+const PH_MEM_SIZE: usize = 52 + 20;
+
+fn set_u32(elf: &mut [u8], offset: usize, value: u32) {
+    elf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[test]
+fn loads_a_minimal_elf() {
+    let image = Image::from_bytes(&minimal_elf()).expect("failed to parse the image");
+    assert_eq!(image.entry, ENTRY);
+    assert_eq!(image.segments.len(), 1);
+    let segment = &image.segments[0];
+    assert_eq!(segment.addr, ENTRY);
+    assert_eq!(segment.data, EBREAK.to_le_bytes());
+    assert!(segment.is_executable);
+    assert!(!segment.is_writable);
+    assert_eq!(image.entry_points(), [ENTRY]);
+}
+
+#[test]
+fn rejects_the_wrong_class() {
+    let mut elf = minimal_elf();
+    elf[4] = 2; // 64-bit.
+    assert!(matches!(
+        Image::from_bytes(&elf),
+        Err(ImageError::Unsupported { .. })
+    ));
+}
+
+#[test]
+fn rejects_the_wrong_machine() {
+    let mut elf = minimal_elf();
+    elf[18..20].copy_from_slice(&62u16.to_le_bytes()); // x86-64.
+    assert!(matches!(
+        Image::from_bytes(&elf),
+        Err(ImageError::Unsupported { .. })
+    ));
+}
+
+#[test]
+fn rejects_a_truncated_elf_header() {
+    let elf = minimal_elf();
+    assert!(matches!(
+        Image::from_bytes(&elf[..40]),
+        Err(ImageError::NotElf)
+    ));
+}
+
+#[test]
+fn rejects_a_truncated_program_header() {
+    let elf = minimal_elf();
+    assert!(matches!(
+        Image::from_bytes(&elf[..60]),
+        Err(ImageError::Truncated { .. })
+    ));
+}
+
+#[test]
+fn rejects_a_segment_larger_in_the_file_than_in_memory() {
+    let mut elf = minimal_elf();
+    set_u32(&mut elf, PH_FILE_SIZE, 8);
+    assert!(matches!(
+        Image::from_bytes(&elf),
+        Err(ImageError::InvalidSegment { addr: ENTRY })
+    ));
+}
+
+#[test]
+fn rejects_a_segment_past_the_end_of_the_address_space() {
+    let mut elf = minimal_elf();
+    set_u32(&mut elf, PH_MEM_SIZE, u32::MAX);
+    assert!(matches!(
+        Image::from_bytes(&elf),
+        Err(ImageError::InvalidSegment { addr: ENTRY })
+    ));
+}
+
+#[test]
+fn rejects_a_bad_section_header_string_table_index() {
+    for index in [0, 2, 0xffff] {
+        let mut elf = minimal_elf();
+        elf[50..52].copy_from_slice(&(index as u16).to_le_bytes());
+        assert!(
+            matches!(
+                Image::from_bytes(&elf),
+                Err(ImageError::InvalidSectionIndex { index: i }) if i == index
+            ),
+            "index {index}"
+        );
+    }
+}
+
+#[test]
+fn huge_uninitialized_data_fails_to_load_without_allocating_it() {
+    // Almost the whole address space, which is more than any memory that the image could be loaded into.
+    let mut elf = minimal_elf();
+    set_u32(&mut elf, PH_MEM_SIZE, 0xffff_0000 - ENTRY);
+    let image = Image::from_bytes(&elf).expect("failed to parse the image");
+    let mut mem = BasicMem::new();
+    assert!(matches!(
+        image.load_into(&mut mem),
+        Err(ImageError::LoadFailed { .. })
+    ));
+}
+
+#[test]
+fn entry_points_are_only_in_code() {
+    // The segment has two instructions in the file, but is zero filled for another two in memory, where there's no
+    // code to find blocks in.
+    let symbol = |name: &str, addr| ImageSymbol {
+        name: name.to_string(),
+        addr,
+        size: 4,
+        is_function: true,
+    };
+    let image = Image {
+        entry: ENTRY,
+        is_compressed: false,
+        segments: vec![Segment {
+            addr: ENTRY,
+            data: [EBREAK, EBREAK]
+                .iter()
+                .flat_map(|ins| ins.to_le_bytes())
+                .collect(),
+            mem_size: 16,
+            is_executable: true,
+            is_writable: false,
+        }],
+        sections: Vec::new(),
+        symbols: vec![
+            symbol("in_code", ENTRY + 4),
+            symbol("in_the_tail", ENTRY + 8),
+        ],
+    };
+    assert_eq!(image.entry_points(), [ENTRY, ENTRY + 4]);
+}
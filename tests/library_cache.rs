@@ -0,0 +1,39 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README.
+
+mod common;
+
+use load_dll::block_finder::*;
+use load_dll::jit::*;
+use load_dll::library_cache::*;
+
+use common::*;
+
+// The start of the loop in `program`, and the breakpoint that it stops at.
+const LOOP: u32 = 0x08;
+const END: u32 = 0x1c;
+
+// Far more than the program needs, so that a runaway guest fails the test rather than hanging it.
+const MAX_INSTRUCTIONS: u64 = 10_000;
+
+#[test]
+fn compilers_sharing_a_cache_entry_get_their_own_copies() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+    let cache_dir = temp_dir("library_cache");
+    let new_compiler = || {
+        let cache = LibraryCache::new(cache_dir.path());
+        BlockCompiler::new(temp_dir("library_cache")).with_cache(cache)
+    };
+    let mut first = new_compiler();
+    let mut second = new_compiler();
+    first.compile(&regions, 0).expect("failed to compile");
+    second.compile(&regions, 0).expect("failed to compile");
+    assert_ne!(first.get(0), second.get(0));
+
+    // Disabling the loop in the first compiler's library leaves the second's alone, so it still runs to the end.
+    first.compiled_block(LOOP).expect("no loop").unlink();
+    let mut cpu = new_cpu(&code);
+    let mut budget = Budget::new(MAX_INSTRUCTIONS);
+    assert_eq!(second.run_chained(&mut cpu, &mut budget, &regions, 0), END);
+    assert!(cpu.is_trapped());
+}
@@ -0,0 +1,103 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README. They check
+// that libraries have really been unloaded by looking for them in the process's memory map, which needs Linux.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use load_dll::block_finder::*;
+use load_dll::code_watch::*;
+use load_dll::compile_worker::*;
+use load_dll::jit::*;
+use std::path::{Path, PathBuf};
+
+use common::*;
+
+// Far enough from the first copy of the program to be in another page.
+const SECOND_COPY: u32 = 4 * PAGE_SIZE;
+
+fn is_mapped(path: &Path) -> bool {
+    let maps = std::fs::read_to_string("/proc/self/maps").expect("failed to read the memory map");
+    maps.lines()
+        .any(|line| line.ends_with(path.to_string_lossy().as_ref()))
+}
+
+// The memory map has the real paths, so resolve any symbolic links in the temporary directory's path.
+fn mapped_paths<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .map(|path| path.canonicalize().expect("failed to find the library"))
+        .collect()
+}
+
+#[test]
+fn library_is_unloaded_with_its_last_block() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+    let mut compiler = BlockCompiler::new(temp_dir("library_unloading"));
+    compiler.compile(&regions, 0).expect("failed to compile");
+    let paths = mapped_paths(compiler.libraries().paths());
+    assert_eq!(paths.len(), 1);
+    assert!(is_mapped(&paths[0]));
+
+    // The program is all in one page, so writing to any of it makes every block stale.
+    let cpu = new_cpu(&code);
+    let change = CodeChange::Write { addr: 0x20, len: 4 };
+    assert!(compiler.code_changed(change, &cpu, &regions) > 0);
+    assert!(compiler.get(0).is_none());
+    assert!(compiler.libraries().is_empty());
+    assert!(!is_mapped(&paths[0]));
+}
+
+#[test]
+fn library_stays_loaded_while_it_has_blocks() {
+    let code = program();
+    let regions = [
+        CodeRegion::new(0, &code),
+        CodeRegion::new(SECOND_COPY, &code),
+    ];
+    let mut compiler = BlockCompiler::new(temp_dir("library_unloading"));
+    compiler.compile(&regions, 0).expect("failed to compile");
+    compiler
+        .compile(&regions, SECOND_COPY)
+        .expect("failed to compile");
+    let paths = mapped_paths(compiler.libraries().paths());
+    assert_eq!(paths.len(), 2);
+
+    // Only the first copy's blocks are stale, so only its library goes.
+    let cpu = new_cpu(&code);
+    let change = CodeChange::Write { addr: 0, len: 4 };
+    assert!(compiler.code_changed(change, &cpu, &regions) > 0);
+    assert!(compiler.get(0).is_none());
+    assert!(compiler.get(SECOND_COPY).is_some());
+    assert_eq!(compiler.libraries().len(), 1);
+    assert!(!is_mapped(&paths[0]));
+    assert!(is_mapped(&paths[1]));
+}
+
+#[test]
+fn shared_library_stays_loaded_while_a_snapshot_has_its_blocks() {
+    let code = program();
+    let regions = [CodeRegion::new(0, &code)];
+    let mut compiler =
+        AsyncCompiler::new(temp_dir("library_unloading"), &regions).expect("failed to start");
+    compiler.queue(0).expect("failed to queue");
+    compiler.wait().expect("failed to compile");
+    let shared = compiler.shared_block_map().clone();
+    let paths = shared.library_paths();
+    let paths = mapped_paths(paths.iter().map(PathBuf::as_path));
+    assert_eq!(paths.len(), 1);
+
+    // Another dispatcher could be running the blocks in its snapshot, so they must stay loaded until it's dropped.
+    let snapshot = shared.snapshot();
+    let cpu = new_cpu(&code);
+    let change = CodeChange::Write { addr: 0, len: 4 };
+    assert!(compiler.code_changed(change, &cpu) > 0);
+    assert!(compiler.get(0).is_none());
+    assert!(snapshot.contains_key(&0));
+    assert!(is_mapped(&paths[0]));
+
+    drop(snapshot);
+    assert_eq!(shared.unload_unused(), 1);
+    assert!(shared.library_paths().is_empty());
+    assert!(!is_mapped(&paths[0]));
+}
@@ -0,0 +1,90 @@
+// These tests compile blocks with rustc, so they need the arviss rlib from the same build. See the README.
+
+mod common;
+
+use arviss::decoding::Reg;
+use arviss::DispatchRv32ic;
+use load_dll::block_finder::*;
+use load_dll::code_watch::*;
+use load_dll::jit::*;
+
+use common::*;
+
+// In the page after the main loop, so that rewriting it leaves the main loop's blocks alone.
+const INCREMENT: u32 = PAGE_SIZE;
+
+// Far more than the program needs, so that a runaway guest fails the test rather than hanging it.
+const MAX_INSTRUCTIONS: u64 = 10_000;
+
+// Jumps to a block in the next page that adds 1 to a0, then rewrites that block to add 100 instead, and does it all
+// again, so a0 ends up as 101. The jump is chained to the block, which is still in the library after it's rewritten.
+fn rewriting_program() -> Vec<u8> {
+    let (a0, a1, a2, a3) = (10, 11, 12, 13);
+    let add_100 = addi(a0, a0, 100);
+    let main = [
+        addi(a0, 0, 0),                             // 00
+        addi(a1, 0, 2),                             // 04
+        lui(a2, add_100.wrapping_add(0x800)),       // 08
+        addi(a2, a2, (add_100 << 20) as i32 >> 20), // 0c
+        lui(a3, INCREMENT),                         // 10
+        jal(0, (INCREMENT - 0x14) as i32),          // 14: loop, to increment
+        sw(a2, a3, 0),                              // 18: rewrite increment
+        addi(a1, a1, -1),                           // 1c
+        bne(a1, 0, -0x0c),                          // 20: to loop
+        EBREAK,                                     // 24
+    ];
+    let increment = [
+        addi(a0, a0, 1),                       // 1000
+        jal(0, 0x18 - (INCREMENT + 4) as i32), // 1004: back to the rewrite
+    ];
+    let mut code: Vec<u8> = main.iter().flat_map(|ins| ins.to_le_bytes()).collect();
+    code.resize(INCREMENT as usize, 0);
+    code.extend(increment.iter().flat_map(|ins| ins.to_le_bytes()));
+    code
+}
+
+// Runs compiled blocks where there are any, and interprets the rest, until the CPU traps. Returns a0.
+fn run(superblocks: bool) -> u32 {
+    let code = rewriting_program();
+    let regions = [CodeRegion::new(0, &code)];
+    let mut compiler =
+        BlockCompiler::new(temp_dir("self_modifying_code")).with_superblocks(superblocks);
+    compiler.compile(&regions, 0).expect("failed to compile");
+    assert!(compiler.get(INCREMENT).is_some());
+
+    let mut cpu = new_cpu(&code);
+    let mut budget = Budget::new(MAX_INSTRUCTIONS);
+    let mut addr = 0;
+    while !cpu.is_trapped() && !budget.is_exhausted() {
+        if compiler.get(addr).is_some() {
+            addr = compiler.run_chained(&mut cpu, &mut budget, &regions, addr);
+            continue;
+        }
+        let Ok(ins) = cpu.fetch() else {
+            break;
+        };
+        let change = CodeChange::before_interpreting(&cpu, ins);
+        cpu.dispatch(ins);
+        budget.retired += 1;
+        if let Some(change) = change.filter(|_| !cpu.is_trapped()) {
+            compiler.code_changed(change, &cpu, &regions);
+        }
+        addr = cpu.transfer();
+    }
+    assert!(cpu.is_trapped(), "the program didn't finish");
+
+    // Only the rewritten block was dropped.
+    assert!(compiler.get(INCREMENT).is_none());
+    assert!(compiler.get(0x14).is_some());
+    cpu.rx(Reg::from(10))
+}
+
+#[test]
+fn chained_block_does_not_run_stale_code() {
+    assert_eq!(run(false), 101);
+}
+
+#[test]
+fn superblock_does_not_run_stale_code() {
+    assert_eq!(run(true), 101);
+}